@@ -0,0 +1,241 @@
+//! Criterion benchmarks of the hot paths of the cache, and a report of the hit ratio on
+//! seeded workloads to catch regressions of the eviction policy
+//!
+//! Run with `cargo bench --bench s3fifo`, the hit ratios are printed before the timings
+
+// `criterion_group!` generates an undocumented function
+#![allow(missing_docs)]
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Zipf};
+use s3fifo::S3FIFO;
+
+/// Capacities of the benchmarked caches
+const CAPACITIES: [usize; 3] = [1_000, 10_000, 100_000];
+
+/// Operations timed by one iteration of a benchmark
+const OPS: usize = 10_000;
+
+/// Seed of the workloads, fixed such that the hit ratios are comparable across runs
+const SEED: u64 = 42;
+
+/// Distribution of the keys of a workload
+#[derive(Clone, Copy)]
+enum Distr {
+    Uniform,
+    Zipf,
+}
+
+impl Distr {
+    fn name(self) -> &'static str {
+        match self {
+            Distr::Uniform => "uniform",
+            Distr::Zipf => "zipf",
+        }
+    }
+
+    /// `len` keys drawn from `0..universe`
+    fn keys(self, universe: u64, len: usize) -> Vec<u64> {
+        let mut rng = StdRng::seed_from_u64(SEED);
+        match self {
+            Distr::Uniform => (0..len).map(|_| rng.gen_range(0..universe)).collect(),
+            Distr::Zipf => {
+                let zipf = Zipf::new(universe, 0.99).unwrap();
+                (0..len).map(|_| zipf.sample(&mut rng) as u64 - 1).collect()
+            }
+        }
+    }
+}
+
+/// Replay the keys by getting each of them and putting it on a miss, the access
+/// pattern of a read-through cache. Returns the hit ratio
+fn replay(cache: &mut S3FIFO<u64, u64>, keys: &[u64]) -> f64 {
+    let mut hits = 0;
+    for &key in keys {
+        if cache.get(&key).is_some() {
+            hits += 1;
+        } else {
+            cache.put(key, key);
+        }
+    }
+    hits as f64 / keys.len() as f64
+}
+
+/// A cache with capacity `cap` that went through a zipf workload, such that both FIFOs
+/// and the ghost FIFO are in a steady state
+fn warm_cache(cap: usize) -> S3FIFO<u64, u64> {
+    let mut cache = S3FIFO::new(cap);
+    replay(&mut cache, &Distr::Zipf.keys(cap as u64 * 100, cap * 20));
+    cache
+}
+
+/// A cache with capacity `cap` whose main FIFO is full, and up to `OPS` keys that are
+/// absent but remembered by its ghost FIFO
+fn ghost_hits(cap: usize) -> (S3FIFO<u64, u64>, Vec<u64>) {
+    let mut cache = S3FIFO::new(cap);
+    let main = cache.main_capacity() as u64;
+    let small = (cap as u64) - main;
+    let cap = cap as u64;
+    // The small FIFO evicts `0..main` to the ghost FIFO, then they fill the main FIFO
+    for key in (0..cap).chain(0..main) {
+        cache.put(key, key);
+    }
+    // The small FIFO evicts `main..2 * cap - small`, the newest of them stay in the
+    // ghost FIFO
+    for key in cap..2 * cap {
+        cache.put(key, key);
+    }
+    let len = (main / 2).min(OPS as u64);
+    let end = 2 * cap - small;
+    (cache, (end - len..end).collect())
+}
+
+fn bench_get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+    group.throughput(Throughput::Elements(OPS as u64));
+    for cap in CAPACITIES {
+        let mut cache = warm_cache(cap);
+        let present: Vec<u64> = cache.iter().map(|(&k, _)| k).collect();
+        let hits: Vec<u64> = present.iter().copied().cycle().take(OPS).collect();
+        // The warm up only draws keys below `cap * 100`
+        let misses: Vec<u64> = (0..OPS as u64).map(|i| cap as u64 * 100 + i).collect();
+
+        group.bench_function(format!("hit/{cap}"), |b| {
+            b.iter(|| {
+                for key in &hits {
+                    black_box(cache.get(key));
+                }
+            })
+        });
+        group.bench_function(format!("miss/{cap}"), |b| {
+            b.iter(|| {
+                for key in &misses {
+                    black_box(cache.get(key));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_put(c: &mut Criterion) {
+    let mut group = c.benchmark_group("put");
+    for cap in CAPACITIES {
+        // Fresh keys into an empty cache, as many as the small FIFO holds: no eviction
+        let empty = S3FIFO::<u64, u64>::new(cap);
+        let len = (cap - empty.main_capacity()).min(OPS) as u64;
+        group.throughput(Throughput::Elements(len));
+        group.bench_function(format!("new_key/{cap}"), |b| {
+            b.iter_batched(
+                || empty.clone(),
+                |mut cache| {
+                    for key in 0..len {
+                        cache.put(key, key);
+                    }
+                    cache
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        // Fresh keys into a full cache: each of them enters the small FIFO, which
+        // evicts its front to the ghost FIFO or promotes it to the main FIFO
+        group.throughput(Throughput::Elements(OPS as u64));
+        let mut cache = warm_cache(cap);
+        let mut next = u64::MAX / 2;
+        group.bench_function(format!("evict_small/{cap}"), |b| {
+            b.iter(|| {
+                for _ in 0..OPS {
+                    next += 1;
+                    cache.put(next, next);
+                }
+            })
+        });
+
+        // Keys the ghost FIFO remembers into a full main FIFO: each of them enters the
+        // main FIFO, which evicts its front
+        let (setup, keys) = ghost_hits(cap);
+        group.throughput(Throughput::Elements(keys.len() as u64));
+        group.bench_function(format!("evict_main/{cap}"), |b| {
+            b.iter_batched(
+                || setup.clone(),
+                |mut cache| {
+                    for &key in &keys {
+                        cache.put(key, key);
+                    }
+                    cache
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_remove(c: &mut Criterion) {
+    let mut group = c.benchmark_group("remove");
+    for cap in CAPACITIES {
+        // Present keys in a random order, removed from wherever they sit in the FIFOs
+        let setup = warm_cache(cap);
+        let mut keys: Vec<u64> = setup.keys().copied().collect();
+        keys.shuffle(&mut StdRng::seed_from_u64(SEED));
+        keys.truncate(OPS);
+        group.throughput(Throughput::Elements(keys.len() as u64));
+        group.bench_function(format!("random/{cap}"), |b| {
+            b.iter_batched(
+                || setup.clone(),
+                |mut cache| {
+                    for key in &keys {
+                        black_box(cache.remove(key));
+                    }
+                    cache
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_replay(c: &mut Criterion) {
+    let mut group = c.benchmark_group("replay");
+    for distr in [Distr::Uniform, Distr::Zipf] {
+        for cap in CAPACITIES {
+            let keys = distr.keys(cap as u64 * 100, cap * 10);
+            group.throughput(Throughput::Elements(keys.len() as u64));
+            group.bench_function(format!("{}/{cap}", distr.name()), |b| {
+                b.iter_batched(
+                    || S3FIFO::new(cap),
+                    |mut cache| replay(&mut cache, &keys),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Print the hit ratios of the seeded workloads. They do not depend on the machine, so
+/// any change of them comes from a change of the eviction policy
+fn report_hit_ratio(_: &mut Criterion) {
+    for distr in [Distr::Uniform, Distr::Zipf] {
+        for cap in CAPACITIES {
+            let keys = distr.keys(cap as u64 * 100, cap * 20);
+            let hit_ratio = replay(&mut S3FIFO::new(cap), &keys);
+            println!("hit ratio/{}/{cap}: {hit_ratio:.6}", distr.name());
+        }
+    }
+}
+
+criterion_group!(
+    benches,
+    report_hit_ratio,
+    bench_get,
+    bench_put,
+    bench_remove,
+    bench_replay
+);
+criterion_main!(benches);
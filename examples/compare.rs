@@ -0,0 +1,113 @@
+//! Compare the S3FIFO against other caches on the same zipf key stream.
+//!
+//! Run with `cargo run --release --example compare --features compare`
+
+use rand::Rng;
+use s3fifo::S3FIFO;
+use std::cell::RefCell;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+const UPPER_BOUND: u64 = 1_000_000;
+const ZIPF_EXPONENT: f64 = 0.99;
+
+/// Result of replaying the key stream against one cache
+struct Report {
+    name: &'static str,
+    hit_count: usize,
+    elapsed: Duration,
+}
+
+/// Replay the keys against a cache described by `get` and `put`: each key is looked up
+/// and inserted on a miss
+fn replay(
+    name: &'static str,
+    keys: &[u64],
+    mut get: impl FnMut(u64) -> bool,
+    mut put: impl FnMut(u64),
+) -> Report {
+    let mut hit_count = 0;
+    let now = Instant::now();
+    for &key in keys {
+        if get(key) {
+            hit_count += 1;
+        } else {
+            put(key);
+        }
+    }
+    Report {
+        name,
+        hit_count,
+        elapsed: now.elapsed(),
+    }
+}
+
+fn main() {
+    let cache_cap = UPPER_BOUND as usize / 100;
+    let zipf_distr = rand_distr::Zipf::new(UPPER_BOUND, ZIPF_EXPONENT).unwrap();
+    let keys: Vec<u64> = rand::thread_rng()
+        .sample_iter(zipf_distr)
+        .take(UPPER_BOUND as usize)
+        .map(|key| key as u64)
+        .collect();
+
+    let mut reports = Vec::new();
+
+    let cache = RefCell::new(S3FIFO::<u64, ()>::new(cache_cap));
+    reports.push(replay(
+        "s3fifo",
+        &keys,
+        |key| cache.borrow_mut().get(&key).is_some(),
+        |key| {
+            cache.borrow_mut().put(key, ());
+        },
+    ));
+
+    let cache = foyer::CacheBuilder::new(cache_cap)
+        .with_eviction_config(foyer::S3FifoConfig::default())
+        .build();
+    reports.push(replay(
+        "foyer (S3FIFO)",
+        &keys,
+        |key| cache.get(&key).is_some(),
+        |key| {
+            cache.insert(key, ());
+        },
+    ));
+
+    let cache = RefCell::new(quick_cache::unsync::Cache::new(cache_cap));
+    reports.push(replay(
+        "quick_cache",
+        &keys,
+        |key| cache.borrow_mut().get(&key).is_some(),
+        |key| cache.borrow_mut().insert(key, ()),
+    ));
+
+    let cache = RefCell::new(lru::LruCache::new(NonZeroUsize::new(cache_cap).unwrap()));
+    reports.push(replay(
+        "lru",
+        &keys,
+        |key| cache.borrow_mut().get(&key).is_some(),
+        |key| {
+            cache.borrow_mut().put(key, ());
+        },
+    ));
+
+    println!(
+        "capacity: {cache_cap}, requests: {}, zipf exponent: {ZIPF_EXPONENT}\n",
+        keys.len()
+    );
+    println!(
+        "{:<16} {:>10} {:>14} {:>10}",
+        "cache", "hit ratio", "elapsed", "ns/op"
+    );
+    for report in reports {
+        println!(
+            "{:<16} {:>10.4} {:>14?} {:>10.1}",
+            report.name,
+            report.hit_count as f64 / keys.len() as f64,
+            report.elapsed,
+            report.elapsed.as_nanos() as f64 / keys.len() as f64
+        );
+    }
+}
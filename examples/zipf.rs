@@ -1,8 +1,52 @@
 //! Evaluate the S3FIFO on zipf distribution
 
 use rand::Rng;
-use s3fifo::S3FIFO;
-use std::time::Duration;
+use s3fifo::{Config, SampledAdmission, ShardedS3FIFO, SyncS3FIFO, S3FIFO};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 8;
+const SHARDS: usize = 32;
+
+/// Replay the keys against the cache, putting the missed ones, returns the hit ratio
+fn replay(cache: &mut S3FIFO<u64, ()>, keys: &[u64]) -> f64 {
+    let mut hit_count = 0;
+    for key in keys {
+        if cache.get(key).is_some() {
+            hit_count += 1;
+        } else {
+            cache.put(*key, ());
+        }
+    }
+    hit_count as f64 / keys.len() as f64
+}
+
+/// Replay the keys split over `THREADS` threads against a thread safe cache described
+/// by `get` and `put`, returns the elapsed time and the hit ratio
+fn replay_concurrent(
+    keys: &[u64],
+    get: impl Fn(u64) -> bool + Sync,
+    put: impl Fn(u64) + Sync,
+) -> (Duration, f64) {
+    let hit_count = AtomicUsize::new(0);
+    let now = Instant::now();
+    std::thread::scope(|s| {
+        for chunk in keys.chunks(keys.len().div_ceil(THREADS)) {
+            let (get, put, hit_count) = (&get, &put, &hit_count);
+            s.spawn(move || {
+                for &key in chunk {
+                    if get(key) {
+                        hit_count.fetch_add(1, Ordering::Relaxed);
+                    } else {
+                        put(key);
+                    }
+                }
+            });
+        }
+    });
+    let hit_ratio = hit_count.into_inner() as f64 / keys.len() as f64;
+    (now.elapsed(), hit_ratio)
+}
 
 fn main() {
     let upper_bound: u64 = 1_000_000;
@@ -17,19 +61,19 @@ fn main() {
         .map(|_| iter.next().unwrap() as u64)
         .collect::<Vec<_>>();
 
-    let now = std::time::Instant::now();
+    let now = Instant::now();
     let mut get_time = Duration::default();
     let mut put_time = Duration::default();
     let mut hit_count = 0;
     for key in keys.iter() {
-        let now = std::time::Instant::now();
+        let now = Instant::now();
         let entry = cache.get(key);
         get_time += now.elapsed();
 
         if entry.is_some() {
             hit_count += 1;
         } else {
-            let now = std::time::Instant::now();
+            let now = Instant::now();
             cache.put(*key, ());
             put_time += now.elapsed();
         }
@@ -43,6 +87,56 @@ fn main() {
         put_time
     );
 
+    // Hit ratio with different caps of the frequency counter
+    for max_freq in [1, 3, 7] {
+        let config = Config {
+            max_freq,
+            ..Config::with_capacity(cache_cap)
+        };
+        let mut cache = S3FIFO::<u64, ()>::from_config(config, Default::default()).unwrap();
+        let hit_ratio = replay(&mut cache, &keys);
+        println!("S3FIFO with max_freq {max_freq} hit ratio: {hit_ratio}");
+    }
+
+    // Hit ratio with different promotion thresholds
+    for promotion_threshold in [1, 2] {
+        let config = Config {
+            promotion_threshold,
+            ..Config::with_capacity(cache_cap)
+        };
+        let mut cache = S3FIFO::<u64, ()>::from_config(config, Default::default()).unwrap();
+        let hit_ratio = replay(&mut cache, &keys);
+        println!("S3FIFO with promotion threshold {promotion_threshold} hit ratio: {hit_ratio}");
+    }
+
+    // Hit ratio without counting the accesses in the small FIFO
+    let config = Config {
+        bump_small_on_get: false,
+        ..Config::with_capacity(cache_cap)
+    };
+    let mut cache = S3FIFO::<u64, ()>::from_config(config, Default::default()).unwrap();
+    let hit_ratio = replay(&mut cache, &keys);
+    println!("S3FIFO without bumping the small FIFO hit ratio: {hit_ratio}");
+
+    // Hit ratio of the zipf keys interleaved with a scan of keys used only once, with
+    // and without sampling the admission of the new keys
+    let mixed: Vec<u64> = keys
+        .iter()
+        .enumerate()
+        .flat_map(|(i, &key)| [key, upper_bound + 1 + i as u64])
+        .collect();
+    for probability in [None, Some(0.1)] {
+        let mut cache = S3FIFO::<u64, ()>::new(cache_cap);
+        if let Some(probability) = probability {
+            cache = cache.with_admission(SampledAdmission::new(probability));
+        }
+        // The keys of the scan never hit, half of the trace are zipf keys
+        let hit_ratio = replay(&mut cache, &mixed) * 2.0;
+        println!(
+            "S3FIFO with a scan and admission probability {probability:?} hit ratio of the zipf keys: {hit_ratio}"
+        );
+    }
+
     // // LRU
     // let mut hit_count = 0;
     // let cache = Mutex::new(lru::LruCache::new(
@@ -101,4 +195,27 @@ fn main() {
     //     now.elapsed(),
     //     hit_count as f64 / upper_bound as f64
     // );
+
+    // Thread safe caches, a single lock against independently locked shards
+    let cache = SyncS3FIFO::<u64, ()>::new(cache_cap);
+    let (elapsed, hit_ratio) = replay_concurrent(
+        &keys,
+        |key| cache.get(&key).is_some(),
+        |key| {
+            cache.put(key, ());
+        },
+    );
+    println!("SyncS3FIFO with {THREADS} threads elapsed: {elapsed:?}. hit ratio: {hit_ratio}");
+
+    let cache = ShardedS3FIFO::<u64, ()>::new(cache_cap, SHARDS);
+    let (elapsed, hit_ratio) = replay_concurrent(
+        &keys,
+        |key| cache.get(&key).is_some(),
+        |key| {
+            cache.put(key, ());
+        },
+    );
+    println!(
+        "ShardedS3FIFO with {THREADS} threads and {SHARDS} shards elapsed: {elapsed:?}. hit ratio: {hit_ratio}"
+    );
 }
@@ -0,0 +1,79 @@
+//! Admission policies, deciding whether a new key enters the cache on `put`
+
+/// Decides whether a key put into the cache enters the small FIFO, see
+/// [`S3FIFO::with_admission`](crate::S3FIFO::with_admission).
+///
+/// The policy is only asked about the keys that are neither cached nor remembered by
+/// the ghost FIFO: a remembered key has proven its reuse and always enters the main
+/// FIFO. A rejected key is remembered by the ghost FIFO instead of being cached, so it
+/// is admitted if it comes back before the ghost FIFO forgets it. The one-hit keys of a
+/// large scan therefore stop flooding the small FIFO, while the keys that are reused
+/// still get in on their second put.
+///
+/// The cache owns the policy and a clone of the cache does not get it, since a policy
+/// usually carries the state of a single cache
+pub trait AdmissionPolicy: Send {
+    /// Returns true if the new key with given hash enters the small FIFO. The hash is
+    /// computed by the hash builder of the cache
+    fn admit(&mut self, hash: u64) -> bool;
+}
+
+/// Admit every key, like a cache without an admission policy
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlwaysAdmit;
+
+impl AdmissionPolicy for AlwaysAdmit {
+    #[inline]
+    fn admit(&mut self, _: u64) -> bool {
+        true
+    }
+}
+
+/// Admit each new key with a fixed probability, the others only enter the cache through
+/// the ghost FIFO on their next put.
+///
+/// A probability of 0 admits a key on its second put at the earliest, which shields the
+/// cache from scans at the cost of a miss for every reused key that was rejected. The
+/// higher the probability, the closer the cache behaves to one without the policy. The
+/// sampling draws from a pseudo random sequence mixed with the hash, not from the hash
+/// alone, such that a rejected key is not rejected again for the same reason.
+///
+/// The small FIFO already keeps the keys used only once out of the main FIFO, so the
+/// gain is modest, the zipf example compares the hit ratios under a scan
+#[derive(Debug, Clone)]
+pub struct SampledAdmission {
+    /// Keys are admitted if the draw is below the threshold, out of `1 << 53`
+    threshold: u64,
+    state: u64,
+}
+
+impl SampledAdmission {
+    /// Create a policy that admits a new key with probability `probability`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `probability` is not in `[0, 1]`
+    pub fn new(probability: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&probability),
+            "admission probability must be in [0, 1], got {probability}"
+        );
+        Self {
+            threshold: (probability * (1u64 << 53) as f64) as u64,
+            state: 0,
+        }
+    }
+}
+
+impl AdmissionPolicy for SampledAdmission {
+    #[inline]
+    fn admit(&mut self, hash: u64) -> bool {
+        // SplitMix64, a full period sequence whose output mixes all of the bits
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state ^ hash;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        z >> 11 < self.threshold
+    }
+}
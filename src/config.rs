@@ -0,0 +1,181 @@
+//! Configuration of the cache
+
+use core::fmt::{self, Display};
+
+/// Default maximum value of the frequency counter of a bucket
+pub(crate) const DEFAULT_MAX_FREQ: u8 = 3;
+
+/// Capacity of a cache created by `S3FIFO::default`, small enough to embed in structs
+/// that derive `Default` and large enough for the FIFOs to be useful
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// Default upper bound of the capacity of the ghost FIFO, about 80MB of hashes
+pub const DEFAULT_MAX_GHOST_SIZE: usize = 1 << 22;
+
+/// Configuration of a [`S3FIFO`](crate::S3FIFO), built with
+/// [`S3FIFO::from_config`](crate::S3FIFO::from_config).
+///
+/// Convenient when the settings come from a deserialized configuration file: start
+/// from [`Config::default`] and override the fields of interest. The main FIFO always
+/// takes the part of the capacity the small FIFO does not, so there is no separate
+/// main ratio to keep in sync:
+///
+/// ```
+/// use s3fifo::{Config, S3FIFO};
+///
+/// let config = Config {
+///     small_ratio: 0.25,
+///     ghost_ratio: 0.5,
+///     ..Config::with_capacity(1000)
+/// };
+/// let cache = S3FIFO::<u64, u64>::from_config(config, Default::default()).unwrap();
+/// assert_eq!(cache.small_capacity(), 250);
+/// assert_eq!(cache.main_capacity(), 750);
+///
+/// let invalid = Config {
+///     small_ratio: 1.5,
+///     ..config
+/// };
+/// assert!(S3FIFO::<u64, u64>::from_config(invalid, Default::default()).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Config {
+    /// Number of entries the cache holds, or their total weight if the cache is
+    /// weighted. A cache with zero capacity never stores anything, otherwise both FIFOs
+    /// have at least one slot
+    pub capacity: usize,
+    /// Fraction of the capacity given to the small FIFO, the main FIFO takes the rest.
+    /// Must be in `(0, 1)`, defaults to 0.1
+    pub small_ratio: f64,
+    /// Capacity of the ghost FIFO relative to the capacity of the main FIFO. Must be
+    /// finite and non-negative, defaults to 1.0
+    pub ghost_ratio: f64,
+    /// Upper bound of the capacity of the ghost FIFO, defaults to
+    /// [`DEFAULT_MAX_GHOST_SIZE`].
+    ///
+    /// Each slot of the ghost FIFO costs about 20 bytes, so a ghost FIFO as large as the
+    /// main FIFO of a cache with 100 million entries would take about 2GB on top of the
+    /// buckets. Bounding it trades some hit ratio on very large caches, whose ghost FIFO
+    /// then remembers fewer evictions, for bounded memory.
+    ///
+    /// The `hash32` feature stores 32 bit hashes and brings a slot down to about 16
+    /// bytes. The ghost FIFO compares hashes rather than keys, so a new key whose hash
+    /// collides with a remembered one is wrongly admitted into the main FIFO. With `n`
+    /// remembered hashes this happens to about `n / 2^32` of the new keys, 0.1% at the
+    /// default bound, against a negligible share with 64 bit hashes. A collision only
+    /// costs hit ratio: the table of the cache compares the keys, so lookups stay exact
+    pub max_ghost_size: usize,
+    /// Maximum value of the frequency counter of a bucket. Must be at least 1, defaults
+    /// to 3
+    pub max_freq: u8,
+    /// Whether `get` and the other accesses bump the frequency of an entry in the small
+    /// FIFO, defaults to true as in the paper.
+    ///
+    /// Without it, only the entries of the main FIFO count their accesses, so the small
+    /// FIFO never promotes: every entry it evicts goes to the ghost FIFO, and a key only
+    /// reaches the main FIFO by coming back while the ghost FIFO remembers it. The hit
+    /// ratio barely moves when the hot keys come back quickly, as in the zipf example.
+    /// Keys that are reused only shortly after their insertion, and then again after the
+    /// ghost FIFO forgot them, lose their hits
+    pub bump_small_on_get: bool,
+    /// Minimum frequency of a bucket at the front of the small FIFO, after its frequency
+    /// is decremented, to be promoted to the main FIFO rather than evicted. Must be at
+    /// least 1, defaults to 1, which promotes the buckets accessed at least twice.
+    ///
+    /// A higher threshold keeps more of the keys that are only accessed a few times out
+    /// of the main FIFO, they have to come back through the ghost FIFO instead. The
+    /// decremented frequency is below `max_freq`, so a threshold of `max_freq` or more
+    /// disables the promotion. The hit ratio barely changes when the hot keys are
+    /// accessed often enough to clear either threshold, as in the zipf example
+    pub promotion_threshold: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            capacity: 0,
+            small_ratio: 0.1,
+            ghost_ratio: 1.0,
+            max_ghost_size: DEFAULT_MAX_GHOST_SIZE,
+            max_freq: DEFAULT_MAX_FREQ,
+            bump_small_on_get: true,
+            promotion_threshold: 1,
+        }
+    }
+}
+
+impl Config {
+    /// Create the default configuration with given capacity
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    /// Check that all of the fields are in their valid range
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(self.small_ratio > 0.0 && self.small_ratio < 1.0) {
+            return Err(ConfigError::SmallRatio(self.small_ratio));
+        }
+        if !(self.ghost_ratio.is_finite() && self.ghost_ratio >= 0.0) {
+            return Err(ConfigError::GhostRatio(self.ghost_ratio));
+        }
+        if self.max_freq == 0 {
+            return Err(ConfigError::MaxFreq);
+        }
+        if self.promotion_threshold == 0 {
+            return Err(ConfigError::PromotionThreshold);
+        }
+        Ok(())
+    }
+
+    /// Capacities of the small, main and ghost FIFOs
+    pub(crate) fn sizes(&self) -> (usize, usize, usize) {
+        if self.capacity == 0 {
+            return (0, 0, 0);
+        }
+        // Both FIFOs have at least one slot, otherwise a small capacity would leave one
+        // of them empty
+        let small_size = ((self.capacity as f64 * self.small_ratio) as usize).max(1);
+        let main_size = (self.capacity - small_size.min(self.capacity)).max(1);
+        let ghost_size = ((main_size as f64 * self.ghost_ratio) as usize).min(self.max_ghost_size);
+        (small_size, main_size, ghost_size)
+    }
+}
+
+/// Error returned when a [`Config`] is invalid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigError {
+    /// `small_ratio` is not in `(0, 1)`
+    SmallRatio(f64),
+    /// `ghost_ratio` is negative or not finite
+    GhostRatio(f64),
+    /// `max_freq` is zero
+    MaxFreq,
+    /// `promotion_threshold` is zero
+    PromotionThreshold,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SmallRatio(ratio) => write!(f, "small ratio must be in (0, 1), got {ratio}"),
+            Self::GhostRatio(ratio) => {
+                write!(
+                    f,
+                    "ghost ratio must be finite and non-negative, got {ratio}"
+                )
+            }
+            Self::MaxFreq => f.write_str("max frequency must be at least 1"),
+            Self::PromotionThreshold => f.write_str("promotion threshold must be at least 1"),
+        }
+    }
+}
+
+impl core::error::Error for ConfigError {}
@@ -0,0 +1,74 @@
+//! Cursor over the entries of the cache
+
+use core::hash::{BuildHasher, Hash};
+
+use hashbrown::DefaultHashBuilder;
+
+use crate::{index_of, S3FIFO};
+
+/// A cursor over the entries of a [`S3FIFO`] that can modify and remove the entry it
+/// points to, created by [`S3FIFO::cursor_front_mut`]. It is loosely modeled on the
+/// `CursorMut` of `LinkedList`.
+///
+/// The cursor starts at the front of the small FIFO and moves towards the back of the
+/// main FIFO. It never bumps the frequencies
+pub struct CursorMut<'a, K, V, S = DefaultHashBuilder> {
+    cache: &'a mut S3FIFO<K, V, S>,
+    in_main: bool,
+    /// Index of the bucket the cursor points to, `None` past the last entry
+    index: Option<usize>,
+}
+
+impl<'a, K, V, S> CursorMut<'a, K, V, S> {
+    pub(crate) fn new(cache: &'a mut S3FIFO<K, V, S>) -> Self {
+        let index = cache.small_fifo.front();
+        let mut cursor = Self {
+            cache,
+            in_main: false,
+            index,
+        };
+        cursor.skip_exhausted_small();
+        cursor
+    }
+
+    /// Get the entry the cursor points to, `None` if the cursor has moved past the
+    /// last entry
+    pub fn current(&mut self) -> Option<(&K, &mut V)> {
+        let bucket = &mut self.cache.buckets[self.index?];
+        Some((&bucket.key, &mut bucket.value))
+    }
+
+    /// Move the cursor to the next entry
+    pub fn move_next(&mut self) {
+        if let Some(index) = self.index {
+            self.index = index_of(self.cache.buckets[index].next);
+            self.skip_exhausted_small();
+        }
+    }
+
+    /// Move to the front of the main FIFO once the small FIFO is exhausted
+    #[inline]
+    fn skip_exhausted_small(&mut self) {
+        if !self.in_main && self.index.is_none() {
+            self.in_main = true;
+            self.index = self.cache.main_fifo.front();
+        }
+    }
+}
+
+impl<K, V, S> CursorMut<'_, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Remove the entry the cursor points to and return it, the cursor then points to
+    /// the entry after it. The removed entry is not recorded in the ghost FIFO
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        let index = self.index?;
+        let next = index_of(self.cache.buckets[index].next);
+        let bucket = self.cache.remove_bucket(index);
+        self.index = next;
+        self.skip_exhausted_small();
+        Some((bucket.key, bucket.value))
+    }
+}
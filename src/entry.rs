@@ -0,0 +1,221 @@
+//! Entry API of the cache, modeled on the one of `HashMap`
+
+use core::hash::{BuildHasher, Hash};
+
+use hashbrown::DefaultHashBuilder;
+
+use crate::{CapacityError, HashValue, NotifyListener, S3FIFO};
+
+/// A view into a single entry of a [`S3FIFO`], which is either present or absent.
+/// Created by [`S3FIFO::entry`].
+///
+/// An entry operation counts as a single access of the key: the frequency of a present
+/// key is bumped at most once, no matter how many of `and_modify` and the `or_insert`
+/// family are chained
+pub enum Entry<'a, K, V, S = DefaultHashBuilder> {
+    /// The key is present in the cache
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    /// The key is absent in the cache
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Get the mutable reference to the value, bumping its frequency like `get` does,
+    /// or insert `default` if the key is absent
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is absent and no room can be made for it, see
+    /// [`VacantEntry::try_insert`]
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    /// Get the mutable reference to the value, bumping its frequency like `get` does,
+    /// or insert the value computed by `f` if the key is absent. `f` is only called on
+    /// a miss
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is absent and no room can be made for it, see
+    /// [`VacantEntry::try_insert`]
+    pub fn or_insert_with<F>(self, f: F) -> &'a mut V
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            Entry::Occupied(mut entry) => {
+                entry.access();
+                entry.into_mut()
+            }
+            Entry::Vacant(entry) => entry.insert(f()),
+        }
+    }
+
+    /// Modify the value in place if the key is present, before any of the `or_insert`
+    /// family. Modifying counts as an access: the frequency is bumped and the access
+    /// callback is fired, exactly like `get` does
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        if let Entry::Occupied(entry) = &mut self {
+            entry.access();
+            f(entry.get_mut());
+        }
+        self
+    }
+
+    /// Key of the entry
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+}
+
+/// A view into a present key of a [`S3FIFO`], part of [`Entry`]
+pub struct OccupiedEntry<'a, K, V, S = DefaultHashBuilder> {
+    cache: &'a mut S3FIFO<K, V, S>,
+    index: usize,
+    /// Whether the access of the entry operation is recorded
+    accessed: bool,
+}
+
+impl<'a, K, V, S> OccupiedEntry<'a, K, V, S> {
+    pub(crate) fn new(cache: &'a mut S3FIFO<K, V, S>, index: usize) -> Self {
+        Self {
+            cache,
+            index,
+            accessed: false,
+        }
+    }
+
+    /// Key of the entry
+    pub fn key(&self) -> &K {
+        &self.cache.buckets[self.index].key
+    }
+
+    /// Reference to the value, without bumping its frequency
+    pub fn get(&self) -> &V {
+        &self.cache.buckets[self.index].value
+    }
+
+    /// Mutable reference to the value, without bumping its frequency
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.cache.buckets[self.index].value
+    }
+
+    /// Convert the entry into the mutable reference to the value, which outlives the
+    /// entry. The frequency is not bumped
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.cache.buckets[self.index].value
+    }
+
+    /// Frequency of the entry
+    pub fn freq(&self) -> u8 {
+        self.cache.buckets[self.index].freq
+    }
+
+    /// Set the frequency of the entry, clamped to the maximum frequency of the cache.
+    /// The frequency decides how many times the entry survives reaching the front of
+    /// its FIFO, so this adjusts its eviction priority directly
+    pub fn set_freq(&mut self, freq: u8) {
+        self.cache.buckets[self.index].freq = freq.min(self.cache.config.max_freq);
+    }
+
+    /// Bump the frequency of the entry like an access, saturated at the maximum
+    /// frequency of the cache. Unlike `and_modify`, the access callback is not fired
+    pub fn bump_freq(&mut self) {
+        self.cache.buckets[self.index].incr_freq(self.cache.config.max_freq);
+    }
+}
+
+impl<K, V, S> OccupiedEntry<'_, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Replace the value like [`S3FIFO::put`] and return the old one. The entry is
+    /// consumed because, in a weighted cache, the new value may evict the entry itself
+    pub fn insert(self, v: V) -> V {
+        #[cfg(feature = "ttl")]
+        self.cache.stamp(self.index, self.cache.ttl);
+        let old = self.cache.replace_value(self.index, v, &mut NotifyListener);
+        self.cache.shed_to_low_watermark(&mut NotifyListener);
+        old
+    }
+
+    /// Remove the entry from the cache and return its value. Like
+    /// [`S3FIFO::remove`], the key is not recorded in the ghost FIFO
+    pub fn remove(self) -> V {
+        self.cache.remove_bucket(self.index).value
+    }
+
+    /// Record the access of the entry operation, once
+    #[inline]
+    fn access(&mut self) {
+        if !self.accessed {
+            self.accessed = true;
+            self.cache.record_access(self.index);
+        }
+    }
+}
+
+/// A view into an absent key of a [`S3FIFO`], part of [`Entry`]
+pub struct VacantEntry<'a, K, V, S = DefaultHashBuilder> {
+    cache: &'a mut S3FIFO<K, V, S>,
+    hash: HashValue,
+    key: K,
+}
+
+impl<'a, K, V, S> VacantEntry<'a, K, V, S> {
+    pub(crate) fn new(cache: &'a mut S3FIFO<K, V, S>, hash: HashValue, key: K) -> Self {
+        Self { cache, hash, key }
+    }
+
+    /// Key of the entry
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Take the ownership of the key
+    pub fn into_key(self) -> K {
+        self.key
+    }
+}
+
+impl<'a, K, V, S> VacantEntry<'a, K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Insert the value with the key of the entry and return the mutable reference to
+    /// it. The insertion goes through the normal admission: the key enters the main
+    /// FIFO if the ghost FIFO remembers it and the small FIFO otherwise
+    ///
+    /// # Panics
+    ///
+    /// Panics if no room can be made for the key, see
+    /// [`try_insert`](VacantEntry::try_insert)
+    pub fn insert(self, v: V) -> &'a mut V {
+        self.try_insert(v).expect("no room can be made for the key")
+    }
+
+    /// Insert the value like [`insert`](VacantEntry::insert), returns `CapacityError`
+    /// and drops the pair if the FIFO that admits the key has zero capacity or is
+    /// lighter than the value
+    pub fn try_insert(self, v: V) -> Result<&'a mut V, CapacityError> {
+        let cache = self.cache;
+        let weight = cache.weigh(&self.key, &v);
+        let index = cache
+            .insert_absent(self.hash, self.key, v, weight, &mut NotifyListener)
+            .ok_or(CapacityError)?;
+        Ok(&mut cache.buckets[index].value)
+    }
+}
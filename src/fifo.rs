@@ -0,0 +1,127 @@
+//! FIFOs of the buckets, linked through the arena
+
+use crate::slab::Slab;
+use crate::Bucket;
+
+/// Link that points to no bucket
+pub(crate) const NIL: u32 = u32::MAX;
+
+/// A FIFO of buckets, a doubly linked list threaded through the `prev` and `next` links
+/// of the buckets in the arena.
+///
+/// The FIFO itself is only its ends and its length, so it never allocates, and a
+/// bucket is unlinked from anywhere in it in constant time given its index. Removing a
+/// key, expiring it or promoting it therefore costs the same wherever the bucket sits.
+/// The links are 32 bit indices, half the size of the `usize` a `VecDeque` of indices
+/// would hold for each bucket, which bounds the arena to `u32::MAX` slots
+#[derive(Clone, Copy)]
+pub(crate) struct LinkedFIFO {
+    /// Index of the front bucket, the next one to evict
+    head: u32,
+    /// Index of the back bucket, the last one pushed
+    tail: u32,
+    len: usize,
+}
+
+impl LinkedFIFO {
+    pub(crate) const fn new() -> Self {
+        Self {
+            head: NIL,
+            tail: NIL,
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Index of the front bucket
+    #[inline]
+    pub(crate) fn front(&self) -> Option<usize> {
+        index_of(self.head)
+    }
+
+    /// Link the bucket at `index` to the back, it must not be in any FIFO
+    #[inline]
+    pub(crate) fn push_back<K, V>(&mut self, buckets: &mut Slab<Bucket<K, V>>, index: usize) {
+        debug_assert!(index < NIL as usize, "Index {index} does not fit in a link");
+        let link = index as u32;
+        let bucket = &mut buckets[index];
+        bucket.prev = self.tail;
+        bucket.next = NIL;
+        match index_of(self.tail) {
+            Some(tail) => buckets[tail].next = link,
+            None => self.head = link,
+        }
+        self.tail = link;
+        self.len += 1;
+    }
+
+    /// Unlink the bucket at `index`, which must be in this FIFO. The order of the other
+    /// buckets is kept
+    #[inline]
+    pub(crate) fn unlink<K, V>(&mut self, buckets: &mut Slab<Bucket<K, V>>, index: usize) {
+        let bucket = &mut buckets[index];
+        let (prev, next) = (bucket.prev, bucket.next);
+        bucket.prev = NIL;
+        bucket.next = NIL;
+        match index_of(prev) {
+            Some(prev) => buckets[prev].next = next,
+            None => self.head = next,
+        }
+        match index_of(next) {
+            Some(next) => buckets[next].prev = prev,
+            None => self.tail = prev,
+        }
+        self.len -= 1;
+    }
+
+    /// Iterate over the indices of the buckets, from the front to the back
+    #[inline]
+    pub(crate) fn iter<'a, K, V>(&self, buckets: &'a Slab<Bucket<K, V>>) -> Indices<'a, K, V> {
+        Indices {
+            buckets,
+            next: self.head,
+            len: self.len,
+        }
+    }
+}
+
+/// Index of the bucket a link points to, `None` for [`NIL`]
+#[inline]
+pub(crate) fn index_of(link: u32) -> Option<usize> {
+    (link != NIL).then_some(link as usize)
+}
+
+/// Iterator over the indices of the buckets of a FIFO, returned by
+/// [`LinkedFIFO::iter`]
+pub(crate) struct Indices<'a, K, V> {
+    buckets: &'a Slab<Bucket<K, V>>,
+    next: u32,
+    /// Number of indices not yielded yet
+    len: usize,
+}
+
+impl<K, V> Iterator for Indices<'_, K, V> {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<usize> {
+        let index = index_of(self.next)?;
+        self.next = self.buckets[index].next;
+        self.len -= 1;
+        Some(index)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
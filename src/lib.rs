@@ -1,263 +1,2764 @@
 //! Implementation of the `S3FIFO` algorithm described in [paper]
 //!
 //! [paper]: https://dl.acm.org/doi/10.1145/3600006.3613147
+//!
+//! # Features
+//!
+//! The `std` feature is enabled by default. Without it the crate is `no_std` and only
+//! needs `alloc`, but it loses the parts built on `std`: the thread safe caches, the
+//! binary snapshot, `S3FIFO::into_hashmap` and the `ttl` feature, which reads the
+//! system clock
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
-use std::collections::VecDeque;
-use std::fmt::Debug;
-use std::hash::{BuildHasher, Hash};
-use std::mem;
-use std::ptr::NonNull;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt::{self, Display};
+use core::hash::{BuildHasher, Hash};
+use core::iter::Chain;
+use core::mem;
+use core::ops::Deref;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "ttl")]
+use std::time::{Duration, Instant};
 
 use hashbrown::hash_table::HashTable;
 use hashbrown::DefaultHashBuilder;
 
+/// Run the statements only when the `stats` feature is enabled, such that the
+/// statistics cost nothing without it
+macro_rules! stats {
+    ($($body:tt)*) => {
+        #[cfg(feature = "stats")]
+        {
+            $($body)*
+        }
+    };
+}
+
+mod admission;
+mod config;
+mod cursor;
+mod entry;
+mod fifo;
+#[cfg(feature = "serde")]
+mod serialize;
+mod slab;
+#[cfg(feature = "std")]
+mod snapshot;
+#[cfg(feature = "stats")]
+mod stats;
+#[cfg(feature = "std")]
+mod sync;
+#[cfg(feature = "ttl")]
+mod ttl;
+
+pub use admission::{AdmissionPolicy, AlwaysAdmit, SampledAdmission};
+pub use config::{Config, ConfigError, DEFAULT_CAPACITY, DEFAULT_MAX_GHOST_SIZE};
+pub use cursor::CursorMut;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+#[cfg(feature = "std")]
+pub use snapshot::ByteCodec;
+#[cfg(feature = "stats")]
+pub use stats::CacheStats;
+#[cfg(feature = "std")]
+pub use sync::{ShardedS3FIFO, SyncS3FIFO};
+#[cfg(feature = "ttl")]
+pub use ttl::{Clock, SystemClock};
+
+use fifo::{index_of, LinkedFIFO, NIL};
+use slab::Slab;
+
 type HashValue = u64;
 
-/// A non-thread safe `S3FIFO` cache
+/// Hash remembered by the ghost FIFO. With the `hash32` feature it is the low half of
+/// the hash of the key, which halves the memory of the ring buffer
+#[cfg(not(feature = "hash32"))]
+type GhostHash = u64;
+#[cfg(feature = "hash32")]
+type GhostHash = u32;
+
+/// A non-thread safe `S3FIFO` cache, see `SyncS3FIFO` for sharing it across threads
+///
+/// Keys and values are stored inline in the slots of an arena, and each slot takes the
+/// size of `K` and `V` plus the weight, the frequency, the FIFO flag and the links of
+/// the FIFO, whether it is occupied or not. The links are 32 bit indices, so a cache
+/// holds at most `u32::MAX - 1` entries.
+/// If `V` is an enum with a rarely used large variant, every slot pays for the large
+/// variant. Storing `Box<V>` instead keeps the slots compact, and [`get_deref`] reads the
+/// boxed value without unwrapping the box at every call site.
+///
+/// The lookups accept any borrowed form of the key, for example `&str` for a `String`
+/// key, as long as `Hash` and `Eq` on the borrowed form match those of the key, like
+/// `HashMap`.
+///
+/// Dropping the cache drops every live key and value exactly once, in the order of the
+/// small FIFO followed by the main FIFO. The entries evicted, replaced or removed
+/// before are dropped or returned at that time.
+///
+/// The ghost FIFO remembers the hashes of the evicted keys rather than the keys, so a
+/// new key whose hash collides with a remembered one is admitted straight into the main
+/// FIFO, as if it had been evicted recently. The table compares the keys, therefore the
+/// lookups stay exact and a collision only costs hit ratio. With the 64 bit hashes of a
+/// randomly seeded hash builder, like the default one, collisions are negligible. A
+/// fixed hash builder lets an adversary pick colliding keys and push them into the main
+/// FIFO, at the expense of the hot keys:
+///
+/// ```
+/// use std::hash::{BuildHasherDefault, Hasher};
+///
+/// use s3fifo::S3FIFO;
+///
+/// /// Hashes every key to the same value
+/// #[derive(Default)]
+/// struct Collide;
+///
+/// impl Hasher for Collide {
+///     fn finish(&self) -> u64 {
+///         0
+///     }
+///
+///     fn write(&mut self, _: &[u8]) {}
+/// }
+///
+/// let mut cache = S3FIFO::with_hasher(10, BuildHasherDefault::<Collide>::default());
+/// cache.put(0, "evicted");
+/// cache.put(1, "evicts 0 from the small FIFO");
+/// assert!(cache.in_ghost(&0));
+///
+/// // 2 was never evicted, but its hash is remembered
+/// assert!(cache.in_ghost(&2));
+/// cache.put(2, "admitted into the main FIFO");
+/// assert_eq!(cache.main_len(), 1);
+///
+/// // The lookups compare the keys
+/// assert_eq!(cache.get(&2), Some(&"admitted into the main FIFO"));
+/// assert_eq!(cache.get(&0), None);
+/// ```
+///
+/// [`get_deref`]: S3FIFO::get_deref
 pub struct S3FIFO<K, V, S = DefaultHashBuilder> {
     hash_builder: S,
-    small_fifo: VecDeque<Bucket<K, V>>,
-    main_fifo: VecDeque<Bucket<K, V>>,
+    /// Arena that owns the buckets. The FIFOs and the table refer to a bucket by its
+    /// index in the arena, which is stable until the bucket is removed. Moving a bucket
+    /// between the FIFOs only relinks it, and growing the arena or the table never
+    /// invalidates the other, so no raw pointer into the buckets is ever kept.
+    ///
+    /// Dropping the cache drops every bucket exactly once through the arena while the
+    /// FIFOs and the table only free their indices, therefore no `Drop` impl is needed
+    /// and the entries can be moved out by value
+    buckets: Slab<Bucket<K, V>>,
+    /// Buckets of the small FIFO, linked through the arena from the front to the back
+    small_fifo: LinkedFIFO,
+    /// Buckets of the main FIFO, linked through the arena from the front to the back
+    main_fifo: LinkedFIFO,
+    /// Capacities of the small and the main FIFO in weight. Without a weigher every
+    /// entry weighs 1, so they are the maximum numbers of entries
+    small_size: usize,
+    main_size: usize,
+    /// Total weights of the entries in the small and the main FIFO
+    small_weight: usize,
+    main_weight: usize,
     ghost_fifo: GhostFIFOCache,
-    table: HashTable<NonNull<Bucket<K, V>>>,
+    /// Indices of the buckets, keyed by the hash of their keys.
+    ///
+    /// The full hash is not stored alongside the index: the control bytes of the
+    /// `HashTable` already filter the probes with 7 bits of the hash, so a probe only
+    /// reads a non-matching bucket for roughly 1/128 of the slots it visits. Storing the
+    /// hash here would double the size of each slot to save very few reads
+    table: HashTable<usize>,
+    config: Config,
+    /// Callback fired when `get`/`get_mut` finds the key
+    on_access: Option<OnAccess<K>>,
+    /// Callback fired with the entries evicted by the S3FIFO policy
+    eviction_listener: Option<EvictionListener<K, V>>,
+    /// Computes the weight of the entries, every entry weighs 1 without it
+    weigher: Option<Weigher<K, V>>,
+    /// Decides whether the new keys that are not in the ghost FIFO enter the cache on
+    /// `put`, every key does without it
+    admission: Option<Box<dyn AdmissionPolicy>>,
+    /// Total weight above which `put` evicts down to `low_watermark`
+    high_watermark: Option<usize>,
+    low_watermark: Option<usize>,
+    #[cfg(feature = "stats")]
+    stats: CacheStats,
+    /// Time to live of the entries put without an explicit one, `None` if they never
+    /// expire
+    #[cfg(feature = "ttl")]
+    ttl: Option<Duration>,
+    #[cfg(feature = "ttl")]
+    clock: Arc<dyn Clock>,
 }
 
+type OnAccess<K> = Box<dyn FnMut(&K) + Send>;
+type EvictionListener<K, V> = Box<dyn FnMut(K, V) + Send>;
+type Weigher<K, V> = Arc<dyn Fn(&K, &V) -> usize + Send + Sync>;
+
 impl<K, V> S3FIFO<K, V, DefaultHashBuilder>
 where
-    K: Eq + Hash + Debug,
+    K: Eq + Hash,
 {
-    /// Create a new `S3FIFO`
+    /// Create a new `S3FIFO`.
+    ///
+    /// The small FIFO takes 1/10 of `cap` and the main FIFO the rest, but each of them
+    /// keeps at least one slot when `cap >= 1`. Capacities below 10 therefore still get
+    /// a working small FIFO of one slot, and the minimum meaningful capacity is 2: one
+    /// slot to admit new keys and one to keep a hot key. A cache with zero capacity never
+    /// stores anything
     pub fn new(cap: usize) -> Self {
         Self::with_hasher(cap, DefaultHashBuilder::default())
     }
+
+    /// Create a new `S3FIFO` that keeps at least `n` hot entries, such that
+    /// `main_capacity() >= n`.
+    ///
+    /// Entries that are accessed again are kept in the main FIFO, while the small FIFO
+    /// is churned by the one-hit misses of a scan. The main FIFO is 9/10 of the
+    /// capacity, so the capacity is `n / 0.9` rounded up, which is `n + n / 9` rounded
+    /// up and saturates rather than overflowing
+    pub fn new_for_hot_entries(n: usize) -> Self {
+        Self::new(n.saturating_add(n.div_ceil(9)))
+    }
+
+    /// Create a new `S3FIFO` whose memory usage is about `bytes`.
+    ///
+    /// The capacity is derived from the size of an arena slot holding a `Bucket<K, V>`
+    /// plus the overhead of the table and the ghost FIFO for each entry. Heap
+    /// memory owned by the keys and values, for example the content of a `String`, is
+    /// ignored. The capacity is at least 1, such that the FIFOs are not empty
+    pub fn with_memory_budget(bytes: usize) -> Self {
+        // A table slot costs the element and a control byte, the table keeps 1/8 of
+        // its slots empty and is over-allocated by `table_capacity`
+        let table_slot =
+            |elem_size: usize| (elem_size + 1) as f64 * 8.0 / 7.0 * table_capacity(1) as f64;
+        let bucket = Slab::<Bucket<K, V>>::SLOT_SIZE as f64 + table_slot(mem::size_of::<usize>());
+        // Ghost FIFO holds 9/10 of the capacity, each hash in the ring buffer and its
+        // sequence number in the table
+        let ghost = mem::size_of::<GhostHash>() as f64 + table_slot(mem::size_of::<u32>());
+        let mut cap = (bytes as f64 / (bucket + 0.9 * ghost)) as usize;
+        if cap / 10 * 9 > DEFAULT_MAX_GHOST_SIZE {
+            // The ghost FIFO is bounded, the rest of the budget goes to the buckets
+            cap = ((bytes as f64 - DEFAULT_MAX_GHOST_SIZE as f64 * ghost) / bucket) as usize;
+        }
+        Self::new(cap.max(1))
+    }
+
+    /// Create a new `S3FIFO` whose capacity is the total weight `max_weight` rather than
+    /// a number of entries, the weight of each entry is computed by `weigher`. See
+    /// [`weighted_from_config`](S3FIFO::weighted_from_config) for the details
+    pub fn new_weighted(
+        max_weight: usize,
+        weigher: impl Fn(&K, &V) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        Self::with_valid_config(
+            Config::with_capacity(max_weight),
+            DefaultHashBuilder::default(),
+            Some(Arc::new(weigher)),
+            false,
+        )
+    }
+
+    /// Create a new `S3FIFO` with capacity `cap` and put all of the key-value pairs
+    /// yielded by `iter` into it.
+    ///
+    /// The pairs are put in order, so a later pair overwrites an earlier one with the
+    /// same key. Once the small FIFO is full, the new keys go to the main FIFO while it
+    /// has room instead of evicting, so all of the pairs are kept if the iterator yields
+    /// at most `cap` distinct keys. A sequence of `put` would only keep the share of the
+    /// small FIFO. The keys beyond `cap` are put as usual: they enter the small FIFO and
+    /// evict the oldest keys it holds, the first ones loaded
+    pub fn from_iter_with_capacity<I>(cap: usize, iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut cache = Self::new(cap);
+        cache.load(iter);
+        cache
+    }
 }
 
 impl<K, V, S> S3FIFO<K, V, S>
 where
-    K: Eq + Hash + Debug,
+    K: Eq + Hash,
     S: BuildHasher,
 {
-    /// Create a new empty `S3FIFO` with hash builder
+    /// Create a new empty `S3FIFO` with hash builder. A cache with zero capacity never
+    /// stores anything
     pub fn with_hasher(cap: usize, hash_builder: S) -> Self {
-        let small_size = cap / 10;
-        let main_size = cap * 9 / 10;
-        let ghost_size = main_size;
+        Self::with_valid_config(Config::with_capacity(cap), hash_builder, None, true)
+    }
+
+    /// Create a new empty `S3FIFO` with the configuration and hash builder, returns
+    /// error if the configuration is invalid
+    pub fn from_config(config: Config, hash_builder: S) -> Result<Self, ConfigError> {
+        config.validate()?;
+        Ok(Self::with_valid_config(config, hash_builder, None, true))
+    }
+
+    /// Create a new empty `S3FIFO` like [`from_config`](S3FIFO::from_config), but
+    /// allocate nothing upfront. For the configurations read from a snapshot, whose
+    /// capacity can not be trusted to size an allocation: the containers only grow with
+    /// the entries actually restored
+    #[cfg(any(feature = "std", feature = "serde"))]
+    pub(crate) fn from_untrusted_config(
+        config: Config,
+        hash_builder: S,
+    ) -> Result<Self, ConfigError> {
+        config.validate()?;
+        Ok(Self::with_valid_config(config, hash_builder, None, false))
+    }
+
+    /// Create a new empty `S3FIFO` whose capacity is a total weight, returns error if the
+    /// configuration is invalid.
+    ///
+    /// `config.capacity` is the weight budget and the FIFOs split it with the usual
+    /// ratios. Each entry is weighed by `weigher` when it is inserted, and the FIFOs
+    /// evict until the summed weight of their entries fits in their share, regardless
+    /// of the number of entries. Nothing is allocated upfront since the number of
+    /// entries is unknown. The ghost FIFO still counts hashes, so with large weights
+    /// `ghost_ratio` should be about the inverse of the typical weight.
+    ///
+    /// An entry heavier than the FIFO that would admit it can never fit and is rejected
+    /// like in a FIFO with zero capacity: `put` drops it and `try_get_or_insert_with`
+    /// returns an error. Replacing a value with a heavier one by `put` evicts to make
+    /// room as well, and removes the entry if the new value can not fit.
+    ///
+    /// The weight is computed when an entry is inserted or its value replaced, and kept
+    /// with the entry until it leaves the cache. Mutating a value through `get_mut`,
+    /// `iter_mut` or the like does not weigh it again
+    pub fn weighted_from_config(
+        config: Config,
+        weigher: impl Fn(&K, &V) -> usize + Send + Sync + 'static,
+        hash_builder: S,
+    ) -> Result<Self, ConfigError> {
+        config.validate()?;
+        Ok(Self::with_valid_config(
+            config,
+            hash_builder,
+            Some(Arc::new(weigher)),
+            false,
+        ))
+    }
+
+    /// Create a new empty `S3FIFO` with the validated configuration. The containers are
+    /// allocated upfront for the number of entries the cache holds if `preallocate` is
+    /// true, which a weighted cache never is
+    fn with_valid_config(
+        config: Config,
+        hash_builder: S,
+        weigher: Option<Weigher<K, V>>,
+        preallocate: bool,
+    ) -> Self {
+        debug_assert!(!preallocate || weigher.is_none());
+        let (small_size, main_size, ghost_size) = config.sizes();
+        let cap = if preallocate {
+            small_size + main_size
+        } else {
+            0
+        };
         S3FIFO {
             hash_builder,
-            small_fifo: VecDeque::with_capacity(small_size),
-            main_fifo: VecDeque::with_capacity(main_size),
-            ghost_fifo: GhostFIFOCache::new(ghost_size),
-            table: HashTable::with_capacity(cap),
+            buckets: Slab::with_capacity(cap),
+            small_fifo: LinkedFIFO::new(),
+            main_fifo: LinkedFIFO::new(),
+            small_size,
+            main_size,
+            small_weight: 0,
+            main_weight: 0,
+            ghost_fifo: GhostFIFOCache::new(ghost_size, preallocate),
+            table: HashTable::with_capacity(table_capacity(cap)),
+            config,
+            on_access: None,
+            eviction_listener: None,
+            weigher,
+            admission: None,
+            high_watermark: None,
+            low_watermark: None,
+            #[cfg(feature = "stats")]
+            stats: CacheStats::default(),
+            #[cfg(feature = "ttl")]
+            ttl: None,
+            #[cfg(feature = "ttl")]
+            clock: Arc::new(SystemClock),
         }
     }
 
     /// Get the value with given key
-    pub fn get(&mut self, k: &K) -> Option<&V> {
-        let hash = self.hash_builder.hash_one(k);
-        self.table
-            .find_mut(hash, |probe_bucket| unsafe {
-                (probe_bucket.as_ref().key).eq(k)
-            })
-            .map(|element| unsafe {
-                element.as_mut().incr_freq();
-                &element.as_mut().value
-            })
+    pub fn get<Q>(&mut self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.access(k).map(|index| &self.buckets[index].value)
+    }
+
+    /// Get the values of a batch of keys, in the order of `keys`, bumping the frequency
+    /// of each key that is found exactly like `get` does. A key that appears twice is
+    /// accessed twice.
+    ///
+    /// All of the keys are accessed before any reference is handed out: bumping a
+    /// frequency needs the cache mutably while the returned references borrow it
+    /// shared, so they can coexist with each other but not with any other call on the
+    /// cache. Clone the values that must outlive the next call.
+    ///
+    /// This is a convenience rather than a speedup: a lookup costs a hash and a probe
+    /// either way, and collecting the results allocates, so a loop of `get` that consumes
+    /// each value right away is slightly faster
+    pub fn get_many<Q>(&mut self, keys: &[Q]) -> Vec<Option<&V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let indices: Vec<_> = keys.iter().map(|k| self.access(k)).collect();
+        indices
+            .into_iter()
+            .map(|index| index.map(|index| &self.buckets[index].value))
+            .collect()
+    }
+
+    /// Get the value with given key and dereference it, for example get `&T` from a
+    /// `S3FIFO<K, Box<T>>`
+    pub fn get_deref<Q>(&mut self, k: &Q) -> Option<&V::Target>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Deref,
+    {
+        self.get(k).map(|v| &**v)
     }
 
     /// Get the mutable reference with given key
-    pub fn get_mut(&mut self, k: &K) -> Option<&mut V> {
+    ///
+    /// The reference points into a bucket owned by the cache. Since it mutably borrows
+    /// the whole cache, no other reference to the cache can coexist with it, and no
+    /// method that evicts or removes buckets can be called until it is dropped:
+    ///
+    /// ```compile_fail
+    /// let mut cache = s3fifo::S3FIFO::new(10);
+    /// cache.put(1, 1);
+    /// let value = cache.get_mut(&1).unwrap();
+    /// cache.put(2, 2);
+    /// *value += 1;
+    /// ```
+    ///
+    /// ```compile_fail
+    /// let mut cache = s3fifo::S3FIFO::new(10);
+    /// cache.put(1, 1);
+    /// let value = cache.get_mut(&1).unwrap();
+    /// let other = cache.get(&1);
+    /// *value += 1;
+    /// ```
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.access(k).map(|index| &mut self.buckets[index].value)
+    }
+
+    /// Run `f` on the mutable reference with given key and return its result. The
+    /// frequency is bumped like [`get_mut`], and the reference does not outlive the call,
+    /// so the cache can be used right after
+    ///
+    /// [`get_mut`]: S3FIFO::get_mut
+    pub fn with_mut<Q, R>(&mut self, k: &Q, f: impl FnOnce(&mut V) -> R) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_mut(k).map(f)
+    }
+
+    /// Run `f` on the mutable reference with given key and return its result, `f`
+    /// returns whether it modified the value. Only a modification counts as an access:
+    /// the frequency is bumped and the access callback is fired only if `f` returns true
+    pub fn get_mut_conditional<Q>(&mut self, k: &Q, f: impl FnOnce(&mut V) -> bool) -> Option<bool>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
         let hash = self.hash_builder.hash_one(k);
-        self.table
-            .find_mut(hash, |probe_bucket| unsafe {
-                (probe_bucket.as_ref().key).eq(k)
-            })
-            .map(|element| unsafe {
-                element.as_mut().incr_freq();
-                &mut element.as_mut().value
-            })
+        let index = self.find_unexpired(hash, k);
+        stats!(self.stats.record_lookup(index.is_some()));
+        let index = index?;
+        let modified = f(&mut self.buckets[index].value);
+        if modified {
+            self.record_access(index);
+        }
+        Some(modified)
+    }
+
+    /// Get the value with given key without bumping its frequency or firing the access
+    /// callback, so inspecting the cache does not influence the eviction
+    pub fn peek<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        self.find_live(hash, k)
+            .map(|index| &self.buckets[index].value)
+    }
+
+    /// Get the mutable reference with given key without bumping its frequency or firing
+    /// the access callback
+    pub fn peek_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        self.find_live(hash, k)
+            .map(|index| &mut self.buckets[index].value)
+    }
+
+    /// Returns true if the key is in the small or the main FIFO, without bumping its
+    /// frequency. A key that is only remembered by the ghost FIFO is not contained, since
+    /// the cache does not hold its value
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        self.find_live(hash, k).is_some()
+    }
+
+    /// Returns true if the ghost FIFO remembers the key, in which case putting the absent
+    /// key admits it straight into the main FIFO. Meant for diagnosing the admission, it
+    /// changes nothing.
+    ///
+    /// The ghost FIFO only remembers hashes, so a key whose hash collides with the one of
+    /// a key evicted recently is reported as well. The answer is independent of whether
+    /// the key is currently cached: a key may be remembered, put again and cached
+    pub fn in_ghost<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.ghost_fifo.contains(self.hash_builder.hash_one(k))
     }
 
     /// Put the key-value pair into the cache. If the cache is has this key present
-    /// the value is updated and return `Some(old)`
+    /// the value is updated and return `Some(old)`.
+    ///
+    /// If the total weight exceeds the high watermark afterwards, the cache evicts down
+    /// to the low watermark, see [`set_high_watermark`](S3FIFO::set_high_watermark)
     pub fn put(&mut self, k: K, v: V) -> Option<V> {
-        if let Some(old) = self.get_mut(&k) {
-            return Some(mem::replace(old, v));
+        let hash = self.hash_builder.hash_one(&k);
+        self.put_hashed(hash, k, v, &mut NotifyListener)
+    }
+
+    /// Put the key-value pair into the cache like [`put`](S3FIFO::put), but only if
+    /// nothing has to allocate or rehash. Otherwise the pair is returned untouched and
+    /// the cache is left as is, for the paths where a latency spike is unacceptable.
+    ///
+    /// Replacing the value of a present key never allocates. An absent key is inserted
+    /// if the arena has a vacant slot or the cache is full, such that the eviction frees
+    /// one, and if the table and the ghost FIFO have room for one more hash. A cache
+    /// sized in entries allocates all of them upfront, so it only fails when a table has
+    /// no growth budget left: the removals leave tombstones that consume the budget
+    /// until the next rehash, which a regular `put` performs. After
+    /// [`shrink_to_fit`](S3FIFO::shrink_to_fit) the allocations are only as large as
+    /// the entries, so absent keys are refused until a removal frees a slot or the
+    /// cache is full again. A weighted cache allocates nothing upfront and the number
+    /// of its entries is not bounded, so it only accepts the values of present keys
+    /// that do not get heavier.
+    ///
+    /// An absent key is also returned if no room can be made for it. The watermarks are
+    /// not enforced, the next `put` sheds the excess
+    pub fn try_put(&mut self, k: K, v: V) -> Result<Option<V>, (K, V)> {
+        let hash = self.hash_builder.hash_one(&k);
+        if let Some(index) = self.find(hash, &k) {
+            if self.weigher.is_some() && self.weigh(&k, &v) > self.buckets[index].weight {
+                return Err((k, v));
+            }
+            #[cfg(feature = "ttl")]
+            self.stamp(index, self.ttl);
+            // A value that is not heavier never evicts
+            return Ok(Some(self.replace_value(index, v, &mut NotifyListener)));
+        }
+
+        // Inserting evicts at most one bucket to the ghost FIFO, the promotions only
+        // relink buckets. The eviction frees a slot of the arena if both FIFOs are full,
+        // since a promotion into a full main FIFO evicts from it
+        let no_grow = self.weigher.is_none()
+            && (self.buckets.has_vacancy() || self.len() >= self.small_size + self.main_size)
+            && self.table.len() < self.table.capacity()
+            && self.ghost_fifo.insert_fits();
+        if !no_grow || !self.has_room(hash, 1) {
+            return Err((k, v));
+        }
+        if self.admits(hash) {
+            self.insert_absent(hash, k, v, 1, &mut NotifyListener);
+        }
+        Ok(None)
+    }
+
+    /// Put the key-value pair into the cache like [`put`](S3FIFO::put), but return the
+    /// pair in [`PutResult::Rejected`] if it can never fit, for example to route it to
+    /// an overflow store.
+    ///
+    /// In a weighted cache, `put` silently drops an absent pair heavier than the FIFO
+    /// that would admit it, and removes a present key whose new value is heavier than
+    /// its FIFO. This method rejects both before anything happens: the cache is not
+    /// modified, and a present key keeps its old value and frequency. In a cache sized
+    /// in entries, only a FIFO with zero capacity rejects.
+    ///
+    /// A new key turned away by the [admission policy](S3FIFO::with_admission) is
+    /// rejected as well, the ghost FIFO remembers it like after `put`
+    pub fn put_checked(&mut self, k: K, v: V) -> PutResult<K, V> {
+        let hash = self.hash_builder.hash_one(&k);
+        let weight = self.weigh(&k, &v);
+        let Some(index) = self.find(hash, &k) else {
+            if !self.has_room(hash, weight) || !self.admits(hash) {
+                return PutResult::Rejected(k, v);
+            }
+            self.insert_absent(hash, k, v, weight, &mut NotifyListener);
+            self.shed_to_low_watermark(&mut NotifyListener);
+            return PutResult::Inserted;
+        };
+
+        let size = if self.buckets[index].in_main {
+            self.main_size
+        } else {
+            self.small_size
+        };
+        if weight > size {
+            return PutResult::Rejected(k, v);
         }
+        #[cfg(feature = "ttl")]
+        self.stamp(index, self.ttl);
+        let old = self.replace_value(index, v, &mut NotifyListener);
+        self.shed_to_low_watermark(&mut NotifyListener);
+        PutResult::Replaced(old)
+    }
 
+    /// Put the key-value pair into the cache like [`put`], and also return the entries
+    /// evicted by this call, for example to log them or write them back to the backing
+    /// store.
+    ///
+    /// Only the entries that leave the cache are returned: making room may promote
+    /// buckets from the small to the main FIFO and decay their frequencies, which can
+    /// cascade into several evictions from the main FIFO, but the promoted and decayed
+    /// buckets stay cached. The evictions to get under the high watermark are returned
+    /// as well. The eviction listener does not see the returned entries. In a weighted
+    /// cache, an entry too heavy for its FIFO is dropped rather than returned
+    ///
+    /// [`put`]: S3FIFO::put
+    pub fn put_evict(&mut self, k: K, v: V) -> (Option<V>, Vec<(K, V)>) {
         let hash = self.hash_builder.hash_one(&k);
+        let mut evicted = Vec::new();
+        let old = self.put_hashed(hash, k, v, &mut |k: K, v: V| evicted.push((k, v)));
+        (old, evicted)
+    }
 
-        if self.ghost_fifo.contains(hash) {
-            if self.main_fifo.len() == self.main_fifo.capacity() {
-                self.evict_main();
+    /// [`put`](S3FIFO::put) with the hash of the key computed by the caller, which must
+    /// use the hash builder of the cache
+    fn put_hashed(
+        &mut self,
+        hash: HashValue,
+        k: K,
+        v: V,
+        on_evict: &mut impl EvictionSink<K, V>,
+    ) -> Option<V> {
+        let old = match self.find(hash, &k) {
+            Some(index) => {
+                #[cfg(feature = "ttl")]
+                self.stamp(index, self.ttl);
+                Some(self.replace_value(index, v, on_evict))
+            }
+            None => {
+                if self.admits(hash) {
+                    let weight = self.weigh(&k, &v);
+                    self.insert_absent(hash, k, v, weight, on_evict);
+                }
+                None
+            }
+        };
+        self.shed_to_low_watermark(on_evict);
+        old
+    }
+
+    /// Put the pairs into a new cache sized in entries like [`put`](S3FIFO::put), except
+    /// that an absent key goes to the main FIFO rather than evicting from a full small
+    /// FIFO, as long as the main FIFO has room
+    fn load(&mut self, iter: impl IntoIterator<Item = (K, V)>) {
+        debug_assert!(self.weigher.is_none());
+        for (k, v) in iter {
+            let hash = self.hash_builder.hash_one(&k);
+            if self.small_weight >= self.small_size
+                && self.main_weight < self.main_size
+                && self.find(hash, &k).is_none()
+            {
+                stats!(self.stats.insertions += 1);
+                self.push_absent(hash, k, v, 1, true);
+            } else {
+                self.put_hashed(hash, k, v, &mut NotifyListener);
+            }
+        }
+    }
+
+    /// Put the key-value pair into the cache like [`put`], but a present key in the small
+    /// FIFO is re-admitted instead of updated in place.
+    ///
+    /// [`put`] keeps the bucket where it is, so an entry at the front of the small FIFO
+    /// may be evicted right after it is written. This method treats the write as strong
+    /// evidence of continued use: the bucket is taken out of the small FIFO and goes
+    /// through the admission again, it is moved to the main FIFO if the ghost FIFO
+    /// contains its key and to the back of the small FIFO otherwise. Keys in the main
+    /// FIFO are updated in place. The watermarks are enforced like by `put`
+    ///
+    /// [`put`]: S3FIFO::put
+    pub fn put_refreshing(&mut self, k: K, v: V) -> Option<V> {
+        let hash = self.hash_builder.hash_one(&k);
+        let old = match self.find(hash, &k) {
+            None => {
+                if self.admits(hash) {
+                    let weight = self.weigh(&k, &v);
+                    self.insert_absent(hash, k, v, weight, &mut NotifyListener);
+                }
+                None
+            }
+            Some(index) if self.buckets[index].in_main => {
+                #[cfg(feature = "ttl")]
+                self.stamp(index, self.ttl);
+                Some(self.replace_value(index, v, &mut NotifyListener))
+            }
+            Some(index) => {
+                // Weighed before the bucket is removed, the weigher may panic
+                let weight = self.weigh(&k, &v);
+                let bucket = self.remove_bucket(index);
+                if let Some(index) =
+                    self.insert_absent(hash, bucket.key, v, weight, &mut NotifyListener)
+                {
+                    let refreshed = &mut self.buckets[index];
+                    refreshed.freq = bucket.freq;
+                    refreshed.incr_freq(self.config.max_freq);
+                }
+                Some(bucket.value)
+            }
+        };
+        self.shed_to_low_watermark(&mut NotifyListener);
+        old
+    }
+
+    /// Shift the split between the small and the main FIFO without changing the total
+    /// capacity, the small FIFO takes `ratio` of it and each FIFO keeps at least one
+    /// slot. The ghost FIFO is left as is.
+    ///
+    /// Shrinking the small FIFO evicts from it with the S3FIFO policy, so entries that
+    /// are accessed are promoted to the main FIFO. Shrinking the main FIFO evicts from it.
+    /// All of the remaining entries stay accessible
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ratio` is not in `(0, 1)`
+    pub fn set_small_ratio(&mut self, ratio: f64) {
+        let config = Config {
+            small_ratio: ratio,
+            ..self.config
+        };
+        if let Err(e) = config.validate() {
+            panic!("{e}");
+        }
+        self.config = config;
+        let (small_size, main_size, _) = config.sizes();
+        stats!(self.stats.resizes += 1);
+
+        if small_size < self.small_size {
+            // Grow main first, such that it has room for the promoted buckets
+            self.main_size = main_size;
+            self.small_size = small_size;
+            while self.small_weight > small_size {
+                self.evict_small(&mut NotifyListener);
             }
-            let bucket = Bucket {
-                key: k,
-                value: v,
-                freq: 0,
-                hash,
-            };
-            self.main_fifo.push_back(bucket);
-            let ptr: NonNull<Bucket<K, V>> = self.main_fifo.back().unwrap().into();
-            self.table
-                .insert_unique(hash, ptr, |bucket| unsafe { bucket.as_ref().hash });
         } else {
-            if self.small_fifo.len() == self.small_fifo.capacity() {
-                self.evict_small();
+            self.main_size = main_size;
+            while self.main_weight > main_size {
+                self.evict_main(&mut NotifyListener);
             }
-            let bucket = Bucket {
-                key: k,
-                value: v,
-                freq: 0,
-                hash,
-            };
-            self.small_fifo.push_back(bucket);
-            let ptr: NonNull<Bucket<K, V>> = self.small_fifo.back().unwrap().into();
+            self.small_size = small_size;
+        }
+    }
+
+    /// Remove the entry with given key from whichever FIFO holds it and return its value.
+    /// The order of the remaining entries is kept.
+    ///
+    /// The removed entry is not recorded in the ghost FIFO, since it was dropped on
+    /// purpose rather than aged out
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        self.remove_hashed(hash, k)
+    }
+
+    /// [`remove`](S3FIFO::remove) with the hash of the key computed by the caller, which
+    /// must use the hash builder of the cache
+    fn remove_hashed<Q>(&mut self, hash: HashValue, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.find_unexpired(hash, k)?;
+        Some(self.remove_bucket(index).value)
+    }
+
+    /// [`get`](S3FIFO::get) with the hash of the key computed by the caller, which must
+    /// use the hash builder of the cache
+    #[cfg(feature = "std")]
+    fn get_hashed<Q>(&mut self, hash: HashValue, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.access_hashed(hash, k)
+            .map(|index| &self.buckets[index].value)
+    }
+
+    /// Remove the entry with given key and return its value if `pred` returns true for
+    /// it, otherwise leave it untouched. The key is probed only once.
+    ///
+    /// The removed entry is not recorded in the ghost FIFO, since it was dropped on
+    /// purpose rather than aged out
+    pub fn take_if<Q>(&mut self, k: &Q, pred: impl FnOnce(&V) -> bool) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        let index = self.find_unexpired(hash, k)?;
+        if pred(&self.buckets[index].value) {
+            Some(self.remove_bucket(index).value)
+        } else {
+            None
+        }
+    }
+
+    /// Remove the bucket at the front of the small FIFO and return its key-value pair.
+    ///
+    /// The front bucket is removed regardless of its frequency, it is neither promoted
+    /// to the main FIFO nor recorded in the ghost FIFO
+    pub fn pop_small(&mut self) -> Option<(K, V)> {
+        let index = self.small_fifo.front()?;
+        let bucket = self.remove_bucket(index);
+        Some((bucket.key, bucket.value))
+    }
+
+    /// Remove the bucket at the front of the main FIFO and return its key-value pair.
+    ///
+    /// The front bucket is removed regardless of its frequency
+    pub fn pop_main(&mut self) -> Option<(K, V)> {
+        let index = self.main_fifo.front()?;
+        let bucket = self.remove_bucket(index);
+        Some((bucket.key, bucket.value))
+    }
+
+    /// Remove all of the entries and reset the ghost FIFO like [`clear`]. Named after
+    /// `moka`'s `invalidate_all`
+    ///
+    /// [`clear`]: S3FIFO::clear
+    pub fn invalidate_all(&mut self) {
+        self.clear();
+    }
+
+    /// Remove all of the entries and reconfigure the cache for capacity `cap`, such that
+    /// it behaves exactly like a freshly constructed cache with the new capacity. The
+    /// ratios, the maximum frequency and the access callback are kept, so a cache created
+    /// with [`new`](S3FIFO::new) ends up identical to `new(cap)`.
+    ///
+    /// The allocations are reused: they are kept as is if the sizes are unchanged and
+    /// resized in place otherwise, which suits object pools that recycle caches with
+    /// varying capacities
+    pub fn reset_to_capacity(&mut self, cap: usize) {
+        self.clear();
+        self.config.capacity = cap;
+        let (small_size, main_size, ghost_size) = self.config.sizes();
+        stats!(self.stats.resizes += 1);
+        self.small_size = small_size;
+        self.main_size = main_size;
+        let preallocate = self.weigher.is_none();
+        let cap = if preallocate {
+            small_size + main_size
+        } else {
+            0
+        };
+        self.buckets.reset_capacity(cap);
+        self.ghost_fifo.reset_capacity(ghost_size, preallocate);
+
+        // The table is empty, the hasher is never called
+        let table_cap = table_capacity(cap);
+        if table_cap > self.table.capacity() {
+            self.table.reserve(table_cap, |_| unreachable!());
+        } else {
+            self.table.shrink_to(table_cap, |_| unreachable!());
+        }
+    }
+
+    /// Change the capacity to `cap` while keeping the entries, the FIFOs and the ghost
+    /// FIFO are resized with the ratios of the cache. Unlike
+    /// [`reset_to_capacity`](S3FIFO::reset_to_capacity), nothing is removed unless it no
+    /// longer fits.
+    ///
+    /// Shrinking evicts with the S3FIFO policy until each FIFO fits, the main FIFO first
+    /// such that it has room for the buckets promoted from the small FIFO, and the ghost
+    /// FIFO forgets its oldest hashes. The evicted entries fire the eviction listener.
+    /// Growing never evicts and reserves the memory for the new capacity upfront, unless
+    /// the cache is weighted. Shrinking keeps the allocations
+    pub fn resize(&mut self, cap: usize) {
+        self.config.capacity = cap;
+        let (small_size, main_size, ghost_size) = self.config.sizes();
+        stats!(self.stats.resizes += 1);
+        self.small_size = small_size;
+        self.main_size = main_size;
+        while self.main_weight > main_size {
+            self.evict_main(&mut NotifyListener);
+        }
+        while self.small_weight > small_size {
+            self.evict_small(&mut NotifyListener);
+        }
+        let preallocate = self.weigher.is_none();
+        self.ghost_fifo.resize(ghost_size, preallocate);
+        if !preallocate {
+            return;
+        }
+
+        let cap = small_size + main_size;
+        self.buckets.reserve(cap);
+        let (buckets, hash_builder) = (&self.buckets, &self.hash_builder);
+        self.table.reserve(
+            table_capacity(cap).saturating_sub(self.table.len()),
+            |&probe| hash_builder.hash_one(&buckets[probe].key),
+        );
+    }
+
+    /// Reserve the memory for at least `additional` more entries, such that a bulk
+    /// load of that many keys does not grow the arena or the table one step at a time.
+    /// The capacity and the eviction are not changed.
+    ///
+    /// The FIFOs are linked through the arena and need no memory of their own. The
+    /// buckets are addressed by their index in the arena, therefore the reallocations
+    /// invalidate nothing
+    pub fn reserve(&mut self, additional: usize) {
+        let total = self.len().saturating_add(additional);
+        self.buckets.reserve(total);
+        let (buckets, hash_builder) = (&self.buckets, &self.hash_builder);
+        self.table.reserve(
+            table_capacity(total).saturating_sub(self.table.len()),
+            |&probe| hash_builder.hash_one(&buckets[probe].key),
+        );
+    }
+
+    /// Release the memory allocated beyond what the entries and the ghost FIFO need, for
+    /// example after a burst of insertions that are since evicted or removed. Only the
+    /// allocations shrink, the capacity of the cache is not changed: the next insertions
+    /// grow them again, and a cache sized in entries gives up the memory it reserved
+    /// upfront. [`try_put`](S3FIFO::try_put) therefore refuses the absent keys until a
+    /// removal frees a slot or `put` fills the cache again.
+    ///
+    /// The buckets are moved to the front of the arena such that its vacant slots can be
+    /// released, and the table is rebuilt for their new indices, which hashes every key
+    pub fn shrink_to_fit(&mut self) {
+        let len = self.buckets.len();
+        // The buckets are moved in FIFO order, so the new index of a bucket is its
+        // position in `hashes`. The keys are hashed before anything is moved
+        let hashes: Vec<HashValue> = self
+            .indices()
+            .map(|index| self.hash_builder.hash_one(&self.buckets[index].key))
+            .collect();
+        let mut buckets = Slab::with_capacity(len);
+        for fifo in [&mut self.small_fifo, &mut self.main_fifo] {
+            let mut moved = LinkedFIFO::new();
+            let mut next = fifo.front();
+            while let Some(index) = next {
+                let bucket = self.buckets.remove(index);
+                next = index_of(bucket.next);
+                let index = buckets.insert(bucket);
+                moved.push_back(&mut buckets, index);
+            }
+            *fifo = moved;
+        }
+        self.buckets = buckets;
+
+        // The table is empty while it shrinks, the hasher is never called
+        self.table.clear();
+        self.table
+            .shrink_to(table_capacity(len), |_| unreachable!());
+        for (index, &hash) in hashes.iter().enumerate() {
             self.table
-                .insert_unique(hash, ptr, |bucket| unsafe { bucket.as_ref().hash });
-        }
-
-        None
-    }
-
-    #[inline]
-    fn evict_small(&mut self) {
-        unsafe {
-            while let Some(mut evicted_bucket) = self.small_fifo.pop_front() {
-                let freq = evicted_bucket.freq.saturating_sub(1);
-                let hash = evicted_bucket.hash;
-                if freq > 0 {
-                    evicted_bucket.freq = freq;
-                    if self.main_fifo.len() == self.main_fifo.capacity() {
-                        self.evict_main();
-                    }
-                    self.main_fifo.push_back(evicted_bucket);
-                    let ptr: NonNull<Bucket<K, V>> = self.main_fifo.back().unwrap().into();
-                    // Update the ptr in the table, because it is in main FIFO now.
-                    // The old ptr is invalid now
-                    match self.table.find_entry(hash, |probe_bucket| {
-                        (probe_bucket.as_ref().key).eq(&ptr.as_ref().key)
-                    }) {
-                        Ok(mut entry) => {
-                            let v = entry.get_mut();
-                            *v = ptr;
-                        }
-                        Err(_) => unreachable!("Key in main FIFO must in table"),
-                    }
-                } else {
-                    self.ghost_fifo.insert(hash);
-                    match self.table.find_entry(evicted_bucket.hash, |probe_bucket| {
-                        (probe_bucket.as_ref().key).eq(&evicted_bucket.key)
-                    }) {
-                        Ok(entry) => {
-                            entry.remove();
-                            return;
-                        }
-                        Err(_) => unreachable!("Key in small FIFO must in table"),
-                    }
+                .insert_unique(hash, index, |&probe| hashes[probe]);
+        }
+        self.ghost_fifo.shrink_to_fit();
+    }
+
+    /// Remove every entry for which `pred` returns true, the order of the remaining
+    /// entries is kept. Named after `moka`'s `invalidate_entries_if`.
+    ///
+    /// The removed entries are not recorded in the ghost FIFO, and the frequencies are
+    /// not bumped
+    pub fn invalidate_entries_if(&mut self, mut pred: impl FnMut(&K, &V) -> bool) {
+        self.remove_buckets_if(|bucket| pred(&bucket.key, &bucket.value));
+    }
+
+    /// Keep only the entries for which `f` returns true, the order of the remaining
+    /// entries is kept. Like [`invalidate_entries_if`](S3FIFO::invalidate_entries_if)
+    /// with the opposite predicate, but `f` may also modify the values it keeps.
+    ///
+    /// The removed entries are not recorded in the ghost FIFO, and the frequencies are
+    /// not bumped
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.remove_buckets_if(|bucket| !f(&bucket.key, &mut bucket.value));
+    }
+
+    /// Put the key-value pair into the cache like [`put`](S3FIFO::put), the entry expires
+    /// once `ttl` has elapsed instead of the default time to live of the cache
+    #[cfg(feature = "ttl")]
+    pub fn put_with_ttl(&mut self, k: K, v: V, ttl: Duration) -> Option<V> {
+        let hash = self.hash_builder.hash_one(&k);
+        let old = match self.find(hash, &k) {
+            Some(index) => {
+                self.stamp(index, Some(ttl));
+                Some(self.replace_value(index, v, &mut NotifyListener))
+            }
+            None if self.admits(hash) => {
+                let weight = self.weigh(&k, &v);
+                if let Some(index) = self.insert_absent(hash, k, v, weight, &mut NotifyListener) {
+                    self.stamp(index, Some(ttl));
                 }
+                None
             }
+            None => None,
+        };
+        self.shed_to_low_watermark(&mut NotifyListener);
+        old
+    }
+
+    /// Remove all of the expired entries and return how many were removed. The lookups
+    /// only reclaim the expired entries they come across, this reclaims the others.
+    ///
+    /// The removed entries are not recorded in the ghost FIFO and do not fire the
+    /// eviction listener
+    #[cfg(feature = "ttl")]
+    pub fn cleanup_expired(&mut self) -> usize {
+        let now = self.clock.now();
+        self.remove_buckets_if(|bucket| bucket.deadline.is_some_and(|deadline| deadline < now))
+    }
+
+    /// Remove every entry put before `cutoff`, whether it expired or not, and return how
+    /// many were removed. A coarse bulk expiry for maintenance tasks, done in one pass
+    /// over the FIFOs.
+    ///
+    /// The removed entries are not recorded in the ghost FIFO and do not fire the
+    /// eviction listener
+    #[cfg(feature = "ttl")]
+    pub fn purge_where_older_than(&mut self, cutoff: Instant) -> usize {
+        self.remove_buckets_if(|bucket| bucket.inserted_at < cutoff)
+    }
+
+    /// Remove the buckets for which `pred` returns true, keeping the order of the
+    /// remaining ones. Returns the number of removed buckets.
+    ///
+    /// `pred` is run on every bucket before any of them is removed, so a panic in it
+    /// leaves the cache untouched
+    fn remove_buckets_if(&mut self, mut pred: impl FnMut(&mut Bucket<K, V>) -> bool) -> usize {
+        let mut indices: Vec<usize> = self.indices().collect();
+        indices.retain(|&index| pred(&mut self.buckets[index]));
+        for &index in &indices {
+            self.remove_bucket(index);
         }
+        indices.len()
     }
 
-    #[inline]
-    fn evict_main(&mut self) {
-        unsafe {
-            while let Some(mut evicted_bucket) = self.main_fifo.pop_front() {
-                let freq = evicted_bucket.freq.saturating_sub(1);
-                if freq > 0 {
-                    evicted_bucket.freq = freq;
-                    let hash = evicted_bucket.hash;
-                    // Insert back to main
-                    self.main_fifo.push_back(evicted_bucket);
-                    let ptr: NonNull<Bucket<K, V>> = self.main_fifo.back().unwrap().into();
-                    // Update the ptr in the table, because it changes its location in the main FIFO.
-                    // The old ptr is invalid now
-                    match self.table.find_entry(hash, |probe_bucket| {
-                        (probe_bucket.as_ref().key).eq(&ptr.as_ref().key)
-                    }) {
-                        Ok(mut entry) => {
-                            let v = entry.get_mut();
-                            *v = ptr;
-                        }
-                        Err(_) => unreachable!("Key in main FIFO must in table"),
-                    }
-                } else {
-                    match self.table.find_entry(evicted_bucket.hash, |probe_bucket| {
-                        (probe_bucket.as_ref().key).eq(&evicted_bucket.key)
-                    }) {
-                        Ok(entry) => {
-                            entry.remove();
-                            return;
-                        }
-                        Err(_) => unreachable!("Key in main FIFO must in table"),
-                    }
+    /// Get the entry of the key for in-place manipulation. The key is hashed and probed
+    /// only once, whatever is done with the entry afterwards.
+    ///
+    /// Getting the entry counts as a lookup in the statistics, but only `and_modify`
+    /// and the `or_insert` family bump the frequency of a present key, like `get` does.
+    /// Inserting through a [`VacantEntry`] goes through the normal admission
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V, S> {
+        let hash = self.hash_builder.hash_one(&k);
+        let index = self.find_unexpired(hash, &k);
+        stats!(self.stats.record_lookup(index.is_some()));
+        match index {
+            Some(index) => Entry::Occupied(OccupiedEntry::new(self, index)),
+            None => Entry::Vacant(VacantEntry::new(self, hash, k)),
+        }
+    }
+
+    /// Get the mutable reference with given key, bumping its frequency, or insert the
+    /// value computed by `f` if the key is absent. The key is hashed and probed only once,
+    /// and `f` is only called on a miss
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is absent and no room can be made for it, see
+    /// [`try_get_or_insert_with`](S3FIFO::try_get_or_insert_with)
+    pub fn get_or_insert_with<F>(&mut self, k: K, f: F) -> &mut V
+    where
+        F: FnOnce() -> V,
+    {
+        self.try_get_or_insert_with(k, || Ok::<_, CapacityError>(f()))
+            .expect("no room can be made for the key")
+    }
+
+    /// Get the mutable reference with given key, bumping its frequency, or insert
+    /// `default` if the key is absent. `default` is dropped on a hit, use
+    /// [`get_or_insert_with`](S3FIFO::get_or_insert_with) if it is costly to build
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is absent and no room can be made for it, see
+    /// [`try_get_or_insert_with`](S3FIFO::try_get_or_insert_with)
+    pub fn get_or_insert(&mut self, k: K, default: V) -> &mut V {
+        self.get_or_insert_with(k, || default)
+    }
+
+    /// Get the mutable reference with a borrowed form of the key, bumping its frequency,
+    /// or insert the value computed by `f` under the key built by `make_key` if the key
+    /// is absent. The key is hashed and probed only once, in its borrowed form, and both
+    /// closures are only called on a miss, so a `String` key looked up by `&str` is only
+    /// allocated when it is inserted.
+    ///
+    /// `make_key` must build a key equal to `k`: the bucket is filed under the hash of
+    /// `k`, and a different key could not be found or evicted properly. This is checked
+    /// in debug builds only
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is absent and no room can be made for it, see
+    /// [`try_get_or_insert_with`](S3FIFO::try_get_or_insert_with). Like there, the
+    /// check comes before the closures run, so neither of them is called in vain
+    pub fn get_or_insert_with_key<Q, F>(
+        &mut self,
+        k: &Q,
+        make_key: impl FnOnce() -> K,
+        f: F,
+    ) -> &mut V
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce() -> V,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        let index = self.find_unexpired(hash, k);
+        stats!(self.stats.record_lookup(index.is_some()));
+        let index = match index {
+            Some(index) => {
+                self.bump_on_access(index);
+                index
+            }
+            None => {
+                // The weight is unknown yet, checking with 0 only rules out an empty FIFO
+                assert!(self.has_room(hash, 0), "no room can be made for the key");
+                let key = make_key();
+                debug_assert!(key.borrow() == k, "make_key built another key");
+                let v = f();
+                let weight = self.weigh(&key, &v);
+                self.insert_absent(hash, key, v, weight, &mut NotifyListener)
+                    .expect("no room can be made for the key")
+            }
+        };
+        &mut self.buckets[index].value
+    }
+
+    /// Get the mutable reference with given key, bumping its frequency. If the key is
+    /// absent, insert the value built by the fallible `f` and return the mutable
+    /// reference to it.
+    ///
+    /// If `f` returns `Err`, the error is returned and the cache is left untouched:
+    /// nothing is inserted or evicted, and the ghost FIFO does not change. If the key is
+    /// absent and no room can be made for it, `CapacityError` is converted into `E` and
+    /// returned without calling `f`. This happens when the FIFO that would admit the key
+    /// has zero capacity. An infallible `f` can return `Result<V, CapacityError>`.
+    ///
+    /// # Ordering
+    ///
+    /// Whether room can be made is decided before `f` runs, and the evictions happen
+    /// only after it succeeds. An expensive or side-effecting `f` is therefore never run
+    /// only to have its result rejected, and a failing or panicking `f` costs the cache
+    /// nothing.
+    ///
+    /// A weighted cache can only weigh the value once it exists, so `CapacityError` is
+    /// also returned after calling `f` if the value is heavier than the admitting FIFO
+    pub fn try_get_or_insert_with<F, E>(&mut self, k: K, f: F) -> Result<&mut V, E>
+    where
+        F: FnOnce() -> Result<V, E>,
+        E: From<CapacityError>,
+    {
+        let hash = self.hash_builder.hash_one(&k);
+        let index = self.find_unexpired(hash, &k);
+        stats!(self.stats.record_lookup(index.is_some()));
+        let index = match index {
+            Some(index) => {
+                self.bump_on_access(index);
+                index
+            }
+            None => {
+                // The weight is unknown yet, checking with 0 only rules out an empty FIFO
+                if !self.has_room(hash, 0) {
+                    return Err(CapacityError.into());
                 }
+                let v = f()?;
+                let weight = self.weigh(&k, &v);
+                self.insert_absent(hash, k, v, weight, &mut NotifyListener)
+                    .ok_or(CapacityError)?
+            }
+        };
+        Ok(&mut self.buckets[index].value)
+    }
+
+    /// Get the mutable reference with given key, or insert the value computed by `f` if
+    /// the key is absent. The entries evicted to make room for the insertion are
+    /// returned as well, for example to be written back to the backing store.
+    ///
+    /// Making room for a single insertion may evict more than one entry: promoting the
+    /// hot buckets of the small FIFO may cascade into evictions from the main FIFO, so
+    /// all of them are returned. Nothing is evicted if the key is present. The key is
+    /// hashed and probed only once
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is absent and no room can be made for it, see
+    /// [`try_get_or_insert_with`](S3FIFO::try_get_or_insert_with)
+    pub fn get_or_insert_reporting<F>(&mut self, k: K, f: F) -> (&mut V, Vec<(K, V)>)
+    where
+        F: FnOnce() -> V,
+    {
+        let hash = self.hash_builder.hash_one(&k);
+        let mut evicted = Vec::new();
+        let index = self.find_unexpired(hash, &k);
+        stats!(self.stats.record_lookup(index.is_some()));
+        let index = match index {
+            Some(index) => {
+                self.bump_on_access(index);
+                index
+            }
+            None => {
+                let v = f();
+                let weight = self.weigh(&k, &v);
+                self.insert_absent(hash, k, v, weight, &mut |k: K, v: V| evicted.push((k, v)))
+                    .expect("no room can be made for the key")
             }
+        };
+        (&mut self.buckets[index].value, evicted)
+    }
+
+    /// Get a clone of the value with given key, or insert the value computed by `f` if
+    /// the key is absent and return a clone of it. The frequency is bumped on a hit.
+    ///
+    /// Returning an owned value releases the borrow of the cache immediately, which is
+    /// cheap for values like `Arc<T>`. If no room can be made for the key, the computed
+    /// value is returned without being cached
+    pub fn get_or_insert_with_cloned<F>(&mut self, k: K, f: F) -> V
+    where
+        V: Clone,
+        F: FnOnce() -> V,
+    {
+        let hash = self.hash_builder.hash_one(&k);
+        let index = self.find_unexpired(hash, &k);
+        stats!(self.stats.record_lookup(index.is_some()));
+        if let Some(index) = index {
+            self.bump_on_access(index);
+            return self.buckets[index].value.clone();
         }
+
+        let value = f();
+        let weight = self.weigh(&k, &value);
+        self.insert_absent(hash, k, value.clone(), weight, &mut NotifyListener);
+        value
     }
-}
 
-/// TBD: Should we store the hash value? Or should we recompute it?
-struct Bucket<K, V> {
-    /// Key
-    key: K,
-    /// Value
-    value: V,
-    /// Frequency
-    freq: u8,
-    /// Hash value of the key, used to avoid recomputing the hash value
-    hash: HashValue,
-}
+    /// Consume the cache and collect its entries into a `HashMap`, dropping the
+    /// frequencies and the ghost FIFO
+    #[cfg(feature = "std")]
+    pub fn into_hashmap(self) -> HashMap<K, V> {
+        self.into_iter().collect()
+    }
 
-impl<K, V> Bucket<K, V> {
+    /// Panic if the internal invariants of the cache do not hold, for downstream tests
+    /// that embed the cache. Checks that
+    ///
+    /// - the table has exactly one index for each bucket in the FIFOs, under the hash of
+    ///   its key
+    /// - the arena holds no bucket outside of the FIFOs
+    /// - the weight of each FIFO is the summed weight of its buckets
+    /// - no FIFO exceeds its capacity
+    /// - the table of the ghost FIFO finds each of its hashes
+    ///
+    /// The key of a broken bucket is printed, hence the `Debug` bound that the rest of
+    /// the cache does not need. The method only exists with debug assertions, so the
+    /// tests that also run in release mode put their calls under
+    /// `#[cfg(debug_assertions)]`
+    #[cfg(debug_assertions)]
+    pub fn assert_consistent(&self)
+    where
+        K: core::fmt::Debug,
+    {
+        assert_eq!(
+            self.table.len(),
+            self.small_fifo.len() + self.main_fifo.len(),
+            "table length differs from the number of buckets"
+        );
+        assert_eq!(
+            self.buckets.len(),
+            self.table.len(),
+            "arena length differs from the number of buckets"
+        );
+        let fifos = [
+            (&self.small_fifo, false, self.small_weight, self.small_size),
+            (&self.main_fifo, true, self.main_weight, self.main_size),
+        ];
+        for (fifo, in_main, fifo_weight, size) in fifos {
+            let mut weight = 0;
+            let mut prev = NIL;
+            let mut len = 0;
+            let mut next = fifo.front();
+            while let Some(index) = next {
+                assert!(
+                    self.buckets.contains(index),
+                    "FIFO refers to the vacant slot {index}"
+                );
+                let bucket = &self.buckets[index];
+                assert_eq!(bucket.prev, prev, "broken link of key {:?}", bucket.key);
+                prev = index as u32;
+                len += 1;
+                next = index_of(bucket.next);
+                assert!(
+                    self.table
+                        .find(self.hash_builder.hash_one(&bucket.key), |&probe| {
+                            probe == index
+                        })
+                        .is_some(),
+                    "key {:?} is not in the table",
+                    bucket.key
+                );
+                assert_eq!(
+                    bucket.in_main, in_main,
+                    "key {:?} is in the other FIFO",
+                    bucket.key
+                );
+                weight += bucket.weight;
+            }
+            assert_eq!(len, fifo.len(), "stale length of the FIFO");
+            assert_eq!(weight, fifo_weight, "stale weight of the FIFO");
+            assert!(fifo_weight <= size);
+        }
+        assert!(self.ghost_fifo.len() <= self.ghost_fifo.capacity());
+        assert_eq!(self.ghost_fifo.table.len(), self.ghost_fifo.len());
+        for &hash in &self.ghost_fifo.ring_buffer {
+            assert!(
+                self.ghost_fifo.contains_ghost_hash(hash),
+                "hash {hash} of the ghost FIFO is not in its table"
+            );
+        }
+    }
+
+    /// Remove the bucket at `index` from its FIFO, the table and the arena.
+    ///
+    /// The key is hashed before anything is modified, so a panic in the user code leaves
+    /// the bucket in place, and the rest of the removal runs no user code
+    fn remove_bucket(&mut self, index: usize) -> Bucket<K, V> {
+        let bucket = &self.buckets[index];
+        let hash = self.hash_builder.hash_one(&bucket.key);
+        let weight = bucket.weight;
+        let (fifo, fifo_weight) = if bucket.in_main {
+            (&mut self.main_fifo, &mut self.main_weight)
+        } else {
+            (&mut self.small_fifo, &mut self.small_weight)
+        };
+        fifo.unlink(&mut self.buckets, index);
+        *fifo_weight -= weight;
+        unlink(&mut self.table, hash, index);
+        self.buckets.remove(index)
+    }
+
+    /// Replace the value of the bucket at `index` and bump its frequency, returns the
+    /// old value. In a weighted cache, the FIFO of the bucket evicts until the new value
+    /// fits, which may evict the bucket itself, and the bucket is removed right away if
+    /// the new value is heavier than the FIFO. The evicted entries are passed to
+    /// `on_evict`
+    fn replace_value(&mut self, index: usize, v: V, on_evict: &mut impl EvictionSink<K, V>) -> V {
+        if self.weigher.is_none() {
+            let bucket = &mut self.buckets[index];
+            bucket.incr_freq(self.config.max_freq);
+            return mem::replace(&mut bucket.value, v);
+        }
+
+        let weight = self.weigh(&self.buckets[index].key, &v);
+        let bucket = &mut self.buckets[index];
+        bucket.incr_freq(self.config.max_freq);
+        let old = mem::replace(&mut bucket.value, v);
+        let old_weight = mem::replace(&mut bucket.weight, weight);
+        let in_main = bucket.in_main;
+        let (fifo_weight, size) = if in_main {
+            (&mut self.main_weight, self.main_size)
+        } else {
+            (&mut self.small_weight, self.small_size)
+        };
+        *fifo_weight = *fifo_weight - old_weight + weight;
+        if weight > size {
+            self.remove_bucket(index);
+        } else if in_main {
+            while self.main_weight > self.main_size {
+                self.evict_main(on_evict);
+            }
+        } else {
+            while self.small_weight > self.small_size {
+                self.evict_small(on_evict);
+            }
+        }
+        old
+    }
+
+    /// Weight of the entry, 1 if the cache is not weighted
     #[inline]
-    fn incr_freq(&mut self) {
-        self.freq = (self.freq + 1) & 3;
+    fn weigh(&self, k: &K, v: &V) -> usize {
+        weight_of(&self.weigher, k, v)
     }
-}
 
-/// A ghost fifo cache that only contains the hash
-///
-/// FIXME: Redundant HashValue
-struct GhostFIFOCache {
-    /// Hash table for fast look up
-    table: HashTable<HashValue>,
-    /// FIFO queue contains the pointer to the map. The lifetime of the pointer is the
-    /// lifetime of the K in the map. It is used to preserve the FIFO order of the map
-    ring_buffer: VecDeque<HashValue>,
-}
+    /// Evict from the small FIFO first and then from the main FIFO, until the total
+    /// weight is at most the low watermark. Does nothing unless the high watermark is
+    /// exceeded. The evicted entries are passed to `on_evict`
+    fn shed_to_low_watermark(&mut self, on_evict: &mut impl EvictionSink<K, V>) {
+        let Some(high) = self.high_watermark else {
+            return;
+        };
+        if self.weight() <= high {
+            return;
+        }
 
-impl GhostFIFOCache {
-    fn new(cap: usize) -> Self {
-        Self {
-            table: HashTable::with_capacity(cap),
-            ring_buffer: VecDeque::with_capacity(cap),
+        let low = self.low_watermark.map_or(high, |low| low.min(high));
+        while self.weight() > low {
+            if !self.small_fifo.is_empty() {
+                self.evict_small(on_evict);
+            } else if !self.main_fifo.is_empty() {
+                self.evict_main(on_evict);
+            } else {
+                break;
+            }
         }
     }
 
+    /// Find the bucket with given key, bump its frequency and fire the access callback
     #[inline]
-    fn contains(&self, hash: HashValue) -> bool {
-        self.table.find(hash, |&probe| probe == hash).is_some()
+    fn access<Q>(&mut self, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        self.access_hashed(hash, k)
     }
 
+    /// [`access`](Self::access) with the hash of the key computed by the caller
     #[inline]
-    fn insert(&mut self, hash: HashValue) {
-        if self.contains(hash) {
-            return;
+    fn access_hashed<Q>(&mut self, hash: HashValue, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.find_unexpired(hash, k);
+        stats!(self.stats.record_lookup(index.is_some()));
+        let index = index?;
+        self.record_access(index);
+        Some(index)
+    }
+
+    /// Bump the frequency of the bucket and fire the access callback
+    #[inline]
+    fn record_access(&mut self, index: usize) {
+        self.bump_on_access(index);
+        if let Some(on_access) = &mut self.on_access {
+            on_access(&self.buckets[index].key);
         }
+    }
 
-        if self.ring_buffer.len() == self.ring_buffer.capacity() {
-            // full
-            let garbage_hash = self.ring_buffer.pop_front().unwrap();
-            let entry = self
-                .table
-                .find_entry(garbage_hash, |&probe| probe == garbage_hash)
-                .unwrap();
-            entry.remove();
+    /// Bump the frequency of the accessed bucket, unless it is in the small FIFO and
+    /// the configuration does not count the accesses there
+    #[inline]
+    fn bump_on_access(&mut self, index: usize) {
+        let bucket = &mut self.buckets[index];
+        if bucket.in_main || self.config.bump_small_on_get {
+            bucket.incr_freq(self.config.max_freq);
+        }
+    }
+
+    /// Returns the index of the bucket with given key
+    #[inline]
+    fn find<Q>(&self, hash: HashValue, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.table
+            .find(hash, |&probe| self.buckets[probe].key.borrow().eq(k))
+            .copied()
+    }
+
+    /// Returns the index of the bucket with given key like [`find`](Self::find), an
+    /// expired bucket is removed and reported absent
+    #[inline]
+    fn find_unexpired<Q>(&mut self, hash: HashValue, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.find(hash, k)?;
+        #[cfg(feature = "ttl")]
+        if self.is_expired(index) {
+            self.remove_bucket(index);
+            return None;
+        }
+        Some(index)
+    }
+
+    /// Returns the index of the bucket with given key like [`find`](Self::find), an
+    /// expired bucket is reported absent but stays in the cache
+    #[inline]
+    fn find_live<Q>(&self, hash: HashValue, k: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.find(hash, k)?;
+        #[cfg(feature = "ttl")]
+        if self.is_expired(index) {
+            return None;
         }
+        Some(index)
+    }
 
-        self.ring_buffer.push_back(hash);
+    /// Returns true if the deadline of the bucket at `index` has passed. The clock is
+    /// only read for the buckets that have a deadline
+    #[cfg(feature = "ttl")]
+    #[inline]
+    fn is_expired(&self, index: usize) -> bool {
+        self.buckets[index]
+            .deadline
+            .is_some_and(|deadline| deadline < self.clock.now())
+    }
 
-        self.table.insert_unique(hash, hash, |&probe| probe);
+    /// Record that the bucket at `index` is put now and expires after `ttl`. A deadline
+    /// beyond the range of `Instant` never comes
+    #[cfg(feature = "ttl")]
+    #[inline]
+    fn stamp(&mut self, index: usize, ttl: Option<Duration>) {
+        let now = self.clock.now();
+        let bucket = &mut self.buckets[index];
+        bucket.inserted_at = now;
+        bucket.deadline = ttl.and_then(|ttl| now.checked_add(ttl));
     }
+
+    /// Returns true if the absent key with given hash enters the cache on `put`. A key
+    /// remembered by the ghost FIFO always does, the admission policy decides for the
+    /// others and the ghost FIFO remembers the rejected ones
+    #[inline]
+    fn admits(&mut self, hash: HashValue) -> bool {
+        let Some(admission) = &mut self.admission else {
+            return true;
+        };
+        if self.ghost_fifo.contains(hash) || admission.admit(hash) {
+            return true;
+        }
+        let _ghost_evicted = self.ghost_fifo.insert(hash);
+        stats! {
+            self.stats.admission_rejections += 1;
+            self.stats.ghost_evictions += u64::from(_ghost_evicted);
+        }
+        false
+    }
+
+    /// Returns true if room can be made for an entry with given hash and weight
+    #[inline]
+    fn has_room(&self, hash: HashValue, weight: usize) -> bool {
+        let size = if self.ghost_fifo.contains(hash) {
+            self.main_size
+        } else {
+            self.small_size
+        };
+        size > 0 && weight <= size
+    }
+
+    /// Insert the key-value pair whose key is absent in the cache, returns the index of
+    /// the newly created bucket. The pair is dropped and `None` is returned if the
+    /// FIFO that admits the key has zero capacity or is lighter than `weight`.
+    ///
+    /// The entries evicted to make room are passed to `on_evict`
+    fn insert_absent(
+        &mut self,
+        hash: HashValue,
+        k: K,
+        v: V,
+        weight: usize,
+        on_evict: &mut impl EvictionSink<K, V>,
+    ) -> Option<usize> {
+        let to_main = self.make_room(hash, weight, on_evict)?;
+        Some(self.push_absent(hash, k, v, weight, to_main))
+    }
+
+    /// Evict from the FIFO that admits the absent key with given hash until an entry of
+    /// `weight` fits, passing the evicted entries to `on_evict`. Returns whether the key
+    /// goes to the main FIFO, or `None` if the entry can never fit in that FIFO.
+    ///
+    /// The FIFO is chosen before evicting, because evicting from the small FIFO records
+    /// hashes in the ghost FIFO
+    fn make_room(
+        &mut self,
+        hash: HashValue,
+        weight: usize,
+        on_evict: &mut impl EvictionSink<K, V>,
+    ) -> Option<bool> {
+        if !self.has_room(hash, weight) {
+            return None;
+        }
+
+        let to_main = self.ghost_fifo.contains(hash);
+        stats! {
+            self.stats.insertions += 1;
+            self.stats.ghost_hits += u64::from(to_main);
+        }
+        if to_main {
+            while self.main_weight + weight > self.main_size {
+                self.evict_main(on_evict);
+            }
+        } else {
+            while self.small_weight + weight > self.small_size {
+                self.evict_small(on_evict);
+            }
+        }
+        Some(to_main)
+    }
+
+    /// Push the absent key into the FIFO chosen by [`make_room`](Self::make_room), which
+    /// must have been called right before
+    fn push_absent(&mut self, hash: HashValue, k: K, v: V, weight: usize, to_main: bool) -> usize {
+        let (fifo, fifo_weight, size) = if to_main {
+            (&mut self.main_fifo, &mut self.main_weight, self.main_size)
+        } else {
+            (
+                &mut self.small_fifo,
+                &mut self.small_weight,
+                self.small_size,
+            )
+        };
+
+        assert!(
+            self.buckets.len() < NIL as usize,
+            "the cache holds at most u32::MAX - 1 entries"
+        );
+        #[cfg(feature = "ttl")]
+        let now = self.clock.now();
+        let index = self.buckets.insert(Bucket {
+            key: k,
+            value: v,
+            weight,
+            freq: 0,
+            in_main: to_main,
+            prev: NIL,
+            next: NIL,
+            #[cfg(feature = "ttl")]
+            inserted_at: now,
+            #[cfg(feature = "ttl")]
+            deadline: self.ttl.and_then(|ttl| now.checked_add(ttl)),
+        });
+        *fifo_weight += weight;
+        debug_assert!(*fifo_weight <= size);
+        fifo.push_back(&mut self.buckets, index);
+        // The capacity of the table shrinks as removals leave tombstones, only a change
+        // of the allocation reveals a reallocation
+        #[cfg(feature = "stats")]
+        let allocation_size = self.table.allocation_size();
+        let (buckets, hash_builder) = (&self.buckets, &self.hash_builder);
+        self.table.insert_unique(hash, index, |&probe| {
+            hash_builder.hash_one(&buckets[probe].key)
+        });
+        stats!(
+            self.stats.table_reallocations +=
+                u64::from(self.table.allocation_size() != allocation_size)
+        );
+        index
+    }
+
+    /// Push a bucket read from a snapshot to the back of its FIFO, with the frequency
+    /// clamped to the maximum. Returns the reason if the key is already present or the
+    /// FIFO is full, such that a corrupted snapshot can not break the invariants
+    #[cfg(any(feature = "std", feature = "serde"))]
+    fn restore_bucket(&mut self, k: K, v: V, freq: u8, in_main: bool) -> Result<(), &'static str> {
+        let (len, size) = if in_main {
+            (self.main_fifo.len(), self.main_size)
+        } else {
+            (self.small_fifo.len(), self.small_size)
+        };
+        if len >= size {
+            return Err("FIFO length exceeds its capacity");
+        }
+        let hash = self.hash_builder.hash_one(&k);
+        if self.find(hash, &k).is_some() {
+            return Err("duplicate key");
+        }
+
+        let index = self.push_absent(hash, k, v, 1, in_main);
+        self.buckets[index].freq = freq.min(self.config.max_freq);
+        Ok(())
+    }
+
+    /// Evict one bucket from the small FIFO, promoting the accessed buckets at its front
+    /// to the main FIFO on the way.
+    ///
+    /// Each bucket leaves the small FIFO only once the main FIFO has room for it and its
+    /// key is hashed, so the user code, which may panic, never runs while a bucket is
+    /// out of both FIFOs
+    #[inline]
+    fn evict_small(&mut self, on_evict: &mut impl EvictionSink<K, V>) {
+        while let Some(index) = self.small_fifo.front() {
+            let bucket = &self.buckets[index];
+            let weight = bucket.weight;
+            let freq = bucket.freq.saturating_sub(1);
+            // A main FIFO with zero capacity, or lighter than the bucket, can not take
+            // the promoted bucket
+            if freq >= self.config.promotion_threshold
+                && self.main_size > 0
+                && weight <= self.main_size
+            {
+                while self.main_weight + weight > self.main_size {
+                    self.evict_main(on_evict);
+                }
+                // The bucket stays in the arena, it is only relinked to the main FIFO
+                self.small_fifo.unlink(&mut self.buckets, index);
+                self.small_weight -= weight;
+                self.main_fifo.push_back(&mut self.buckets, index);
+                self.main_weight += weight;
+                let bucket = &mut self.buckets[index];
+                bucket.freq = freq;
+                bucket.in_main = true;
+                stats!(self.stats.promotions_to_main += 1);
+            } else {
+                let hash = self.hash_builder.hash_one(&bucket.key);
+                self.small_fifo.unlink(&mut self.buckets, index);
+                self.small_weight -= weight;
+                let _ghost_evicted = self.ghost_fifo.insert(hash);
+                stats! {
+                    self.stats.evictions_from_small += 1;
+                    self.stats.ghost_evictions += u64::from(_ghost_evicted);
+                }
+                unlink(&mut self.table, hash, index);
+                let bucket = self.buckets.remove(index);
+                on_evict.evicted(&mut self.eviction_listener, bucket.key, bucket.value);
+                return;
+            }
+        }
+    }
+
+    /// Evict one bucket from the main FIFO, reinserting the accessed buckets at its
+    /// front with a decayed frequency on the way
+    #[inline]
+    fn evict_main(&mut self, on_evict: &mut impl EvictionSink<K, V>) {
+        while let Some(index) = self.main_fifo.front() {
+            let bucket = &mut self.buckets[index];
+            let freq = bucket.freq.saturating_sub(1);
+            if freq > 0 {
+                bucket.freq = freq;
+                // Insert back to main
+                self.main_fifo.unlink(&mut self.buckets, index);
+                self.main_fifo.push_back(&mut self.buckets, index);
+            } else {
+                let hash = self.hash_builder.hash_one(&bucket.key);
+                let weight = bucket.weight;
+                self.main_fifo.unlink(&mut self.buckets, index);
+                self.main_weight -= weight;
+                stats!(self.stats.evictions_from_main += 1);
+                unlink(&mut self.table, hash, index);
+                let bucket = self.buckets.remove(index);
+                on_evict.evicted(&mut self.eviction_listener, bucket.key, bucket.value);
+                return;
+            }
+        }
+    }
+}
+
+/// Helpers of a cache that shares its values through `Arc`, such that the values
+/// outlive the borrow of the cache, for example in async tasks
+impl<K, T, S> S3FIFO<K, Arc<T>, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+    T: ?Sized,
+{
+    /// Get an owned handle to the value with given key and bump its frequency, like
+    /// [`get`](S3FIFO::get) followed by `Arc::clone`. The handle keeps the value alive
+    /// after the cache evicts or replaces it
+    pub fn get_arc<Q>(&mut self, k: &Q) -> Option<Arc<T>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(k).map(Arc::clone)
+    }
+
+    /// Wrap the value in an `Arc`, put it like [`put`](S3FIFO::put) and return an owned
+    /// handle to it
+    pub fn put_arc(&mut self, k: K, v: T) -> Arc<T>
+    where
+        T: Sized,
+    {
+        let v = Arc::new(v);
+        self.put(k, Arc::clone(&v));
+        v
+    }
+}
+
+impl<K, V, S> S3FIFO<K, V, S> {
+    /// Register a callback fired with the key whenever [`get`] or [`get_mut`] finds it,
+    /// for feeding external access analytics. Without a callback, the lookups only pay
+    /// for checking that it is absent
+    ///
+    /// # Reentrancy
+    ///
+    /// The callback runs while the cache is mutably borrowed, in the middle of the
+    /// lookup. It can not reach the cache again without going through a shared owner
+    /// with interior mutability, which already rules out reentrant mutation: a
+    /// `RefCell` panics on the second `borrow_mut`, and locking a `std::sync::Mutex`,
+    /// such as the one of a `SyncS3FIFO`, again from the thread that holds it deadlocks
+    /// or panics. Therefore the cache never observes a reentrant call and needs no guard
+    /// of its own, but a callback must not lock the cache that calls it
+    ///
+    /// [`get`]: S3FIFO::get
+    /// [`get_mut`]: S3FIFO::get_mut
+    pub fn with_on_access(mut self, on_access: impl FnMut(&K) + Send + 'static) -> Self {
+        self.on_access = Some(Box::new(on_access));
+        self
+    }
+
+    /// Let `admission` decide whether the new keys enter the cache, to resist the scans
+    /// that would flood the small FIFO with keys used only once. See [`AdmissionPolicy`]
+    /// for which keys it is asked about and what happens to the rejected ones.
+    ///
+    /// Only the `put` family consults the policy. The `get_or_insert` family and the
+    /// entry API always insert, since they return a reference to the value
+    pub fn with_admission(mut self, admission: impl AdmissionPolicy + 'static) -> Self {
+        self.admission = Some(Box::new(admission));
+        self
+    }
+
+    /// Expire the entries `ttl` after they are put, unless they are put with
+    /// [`put_with_ttl`](S3FIFO::put_with_ttl). Only the entries put afterwards get the
+    /// deadline.
+    ///
+    /// An expired entry is a miss: `get` and the other lookups remove it and report it
+    /// absent, and the `get_or_insert` family computes a new value for it. `peek` and
+    /// [`contains_key`](S3FIFO::contains_key) report it absent too, but can not remove
+    /// it, so it still takes room in the cache and is still visited by the iterators
+    /// and counted by `len` until a mutable lookup or
+    /// [`cleanup_expired`](S3FIFO::cleanup_expired) reclaims it, or it is evicted
+    #[cfg(feature = "ttl")]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Replace the [`SystemClock`] that stamps and expires the entries, for example with
+    /// a fake clock that tests advance by hand instead of sleeping
+    #[cfg(feature = "ttl")]
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Register a callback that takes the entries evicted by the S3FIFO policy, for
+    /// releasing the resources they hold like closing a connection or flushing a buffer.
+    ///
+    /// It fires exactly when a bucket whose frequency dropped to 0 leaves the small or
+    /// the main FIFO for good. Promoting a bucket from the small to the main FIFO, or
+    /// reinserting a bucket into the main FIFO with a decayed frequency, does not fire
+    /// it. Neither do the entries that leave the cache on purpose: the ones removed,
+    /// invalidated, popped, drained or cleared, the values replaced by `put`, and the
+    /// entries dropped with the cache. The entries evicted by
+    /// [`get_or_insert_reporting`](S3FIFO::get_or_insert_reporting) are returned to its
+    /// caller instead.
+    ///
+    /// Like the access callback, it runs while the cache is mutably borrowed and can not
+    /// reenter the cache
+    pub fn with_eviction_listener(mut self, listener: impl FnMut(K, V) + Send + 'static) -> Self {
+        self.eviction_listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Number of entries in the small and the main FIFO. The hashes in the ghost FIFO
+    /// are not entries and are not counted
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Returns true if the cache holds no entry
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Capacity requested by the user when the cache was created, a total weight if the
+    /// cache is weighted.
+    ///
+    /// The FIFOs are sized from it, but each of them keeps at least one slot, so the
+    /// cache may hold one more entry than requested when the capacity is tiny. See
+    /// [`small_capacity`](S3FIFO::small_capacity) and
+    /// [`main_capacity`](S3FIFO::main_capacity) for the actual sizes
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.config.capacity
+    }
+
+    /// Remove all of the entries and reset the ghost FIFO, the cache then behaves like a
+    /// freshly created one. The values are dropped right away, while the configuration,
+    /// the hash builder and the allocated capacity are kept, so refilling the cache does
+    /// not allocate
+    pub fn clear(&mut self) {
+        self.table.clear();
+        self.buckets.clear();
+        self.small_fifo = LinkedFIFO::new();
+        self.main_fifo = LinkedFIFO::new();
+        self.small_weight = 0;
+        self.main_weight = 0;
+        self.ghost_fifo.clear();
+    }
+
+    /// Total weight of the entries, the same as [`len`](S3FIFO::len) if the cache is
+    /// not weighted
+    #[inline]
+    pub fn weight(&self) -> usize {
+        self.small_weight + self.main_weight
+    }
+
+    /// Evict down to the low watermark whenever [`put`](S3FIFO::put) leaves the total
+    /// weight above `weight`. Without a low watermark, it evicts down to `weight` itself.
+    ///
+    /// Shedding a batch of entries at once keeps the cache below its capacity, leaving
+    /// headroom for the weight to grow between the puts. The entries are evicted with
+    /// the S3FIFO policy, from the small FIFO first
+    pub fn set_high_watermark(&mut self, weight: usize) {
+        self.high_watermark = Some(weight);
+    }
+
+    /// Total weight that [`put`](S3FIFO::put) evicts down to once the high watermark is
+    /// exceeded, see [`set_high_watermark`](S3FIFO::set_high_watermark). It is clamped to
+    /// the high watermark
+    pub fn set_low_watermark(&mut self, weight: usize) {
+        self.low_watermark = Some(weight);
+    }
+
+    /// Statistics collected since the cache was created or the statistics were reset
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Moving average of the hit ratio over roughly the last 1000 lookups, see
+    /// [`CacheStats::recent_hit_ratio`]
+    #[cfg(feature = "stats")]
+    pub fn recent_hit_ratio(&self) -> f64 {
+        self.stats.recent_hit_ratio
+    }
+
+    /// Reset all of the statistics to zero
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    /// Capacity of the small FIFO, a weight if the cache is weighted
+    pub fn small_capacity(&self) -> usize {
+        self.small_size
+    }
+
+    /// Capacity of the main FIFO, a weight if the cache is weighted
+    pub fn main_capacity(&self) -> usize {
+        self.main_size
+    }
+
+    /// Number of entries in the small FIFO, the keys admitted on their first sight that
+    /// were neither promoted nor evicted yet
+    #[inline]
+    pub fn small_len(&self) -> usize {
+        self.small_fifo.len()
+    }
+
+    /// Number of entries in the main FIFO, the keys promoted from the small FIFO or
+    /// admitted directly because the ghost FIFO remembered them
+    #[inline]
+    pub fn main_len(&self) -> usize {
+        self.main_fifo.len()
+    }
+
+    /// Number of hashes in the ghost FIFO, the keys recently evicted from the small FIFO
+    /// that go straight to the main FIFO if they come back
+    #[inline]
+    pub fn ghost_len(&self) -> usize {
+        self.ghost_fifo.len()
+    }
+
+    /// Estimate of the heap memory allocated by the cache in bytes, for correlating the
+    /// capacity with the resident memory. The counterpart of
+    /// [`with_memory_budget`](S3FIFO::with_memory_budget), but measured on the actual
+    /// allocations rather than predicted.
+    ///
+    /// Included are the arena of the buckets, whose slots hold the keys, the values and
+    /// the links of the FIFOs inline, the table, and the ring buffer and the table of
+    /// the ghost FIFO. Excluded are the heap memory owned by the keys and the values
+    /// themselves, like the buffer of a `String`, the callbacks, the weigher and the
+    /// struct of the cache. A weigher that returns the heap size of the entries makes
+    /// [`weight`](S3FIFO::weight) an estimate of the excluded part of the entries.
+    ///
+    /// The allocations are kept by [`clear`](S3FIFO::clear) and the evictions, only
+    /// [`shrink_to_fit`](S3FIFO::shrink_to_fit) releases them
+    pub fn memory_footprint(&self) -> usize {
+        self.buckets.allocation_size()
+            + self.table.allocation_size()
+            + self.ghost_fifo.allocation_size()
+    }
+
+    /// Fold every value in the cache into an accumulator, in the order of the small FIFO
+    /// followed by the main FIFO, without bumping the frequencies
+    pub fn fold_values<A>(&self, init: A, mut f: impl FnMut(A, &V) -> A) -> A {
+        self.indices()
+            .fold(init, |acc, index| f(acc, &self.buckets[index].value))
+    }
+
+    /// Apply `f` to every entry in the cache, in the order of the small FIFO followed by
+    /// the main FIFO. The frequencies are not bumped, and nothing is moved or evicted
+    pub fn for_each_mut(&mut self, mut f: impl FnMut(&K, &mut V)) {
+        for fifo in [self.small_fifo, self.main_fifo] {
+            let mut next = fifo.front();
+            while let Some(index) = next {
+                let bucket = &mut self.buckets[index];
+                f(&bucket.key, &mut bucket.value);
+                next = index_of(bucket.next);
+            }
+        }
+    }
+
+    /// Iterate over the entries, in the order of the small FIFO followed by the main
+    /// FIFO. The frequencies are not bumped, and the ghost FIFO is not visited since it
+    /// holds no value
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            indices: self.indices(),
+            buckets: &self.buckets,
+        }
+    }
+
+    /// Indices of the buckets, in the order of the small FIFO followed by the main FIFO
+    #[inline]
+    fn indices(&self) -> FIFOIndices<'_, K, V> {
+        self.small_fifo
+            .iter(&self.buckets)
+            .chain(self.main_fifo.iter(&self.buckets))
+    }
+
+    /// Iterate over the entries with mutable references to the values. The frequencies
+    /// are not bumped.
+    ///
+    /// The entries are visited in the order of their slots in the arena rather than in
+    /// the order of the FIFOs, which lets the iterator hand out the mutable references
+    /// without aliasing. Use [`for_each_mut`](S3FIFO::for_each_mut) to visit them in the
+    /// order of [`iter`](S3FIFO::iter)
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut {
+            inner: self.buckets.iter_mut(),
+        }
+    }
+
+    /// Iterate over the keys, in the same order as [`iter`](S3FIFO::iter). The
+    /// frequencies are not bumped
+    pub fn keys(&self) -> impl Iterator<Item = &K> + '_ {
+        self.iter().map(|(k, _)| k)
+    }
+
+    /// Iterate over the values, in the same order as [`iter`](S3FIFO::iter). The
+    /// frequencies are not bumped
+    pub fn values(&self) -> impl Iterator<Item = &V> + '_ {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// Iterate over the mutable references to the values, in the same order as
+    /// [`iter_mut`](S3FIFO::iter_mut). The frequencies are not bumped
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> + '_ {
+        self.iter_mut().map(|(_, v)| v)
+    }
+
+    /// Iterate over the keys with their frequencies and the FIFO that holds them, in the
+    /// same order as [`iter`](S3FIFO::iter). Meant for tuning the policy, for example to
+    /// check the promotions or to build a histogram of the frequencies. Nothing is
+    /// modified
+    pub fn debug_entries(&self) -> impl Iterator<Item = (&K, u8, Fifo)> + '_ {
+        self.indices().map(|index| {
+            let bucket = &self.buckets[index];
+            let fifo = if bucket.in_main {
+                Fifo::Main
+            } else {
+                Fifo::Small
+            };
+            (&bucket.key, bucket.freq, fifo)
+        })
+    }
+
+    /// Remove all of the entries and return them as an iterator, in the order of the
+    /// small FIFO followed by the main FIFO. The ghost FIFO is reset as well.
+    ///
+    /// The allocated capacity is kept, so the cache can be reused without growing. If
+    /// the iterator is dropped before being fully consumed, the remaining entries are
+    /// dropped. The cache is emptied before the first entry is yielded, so it is left
+    /// empty even if the iterator is leaked or dropping an entry panics
+    pub fn drain(&mut self) -> impl Iterator<Item = (K, V)> + '_ {
+        let inner = IntoIter {
+            next: self.small_fifo.front(),
+            main_front: self.main_fifo.front(),
+            buckets: mem::replace(&mut self.buckets, Slab::with_capacity(0)),
+        };
+        self.clear();
+        Drain {
+            inner,
+            arena: &mut self.buckets,
+        }
+    }
+
+    /// Create a cursor at the front of the small FIFO, which can walk through all of
+    /// the entries and modify or remove them
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, K, V, S> {
+        CursorMut::new(self)
+    }
+
+    /// Describe the configuration and fill of the cache in a single line, such as
+    /// `S3FIFO(len=21/1000, small=1/100, main=20/900, ghost=3) { small_len: 1,
+    /// main_len: 20, ghost_capacity: 900, max_freq: 3, features: [ttl, stats] }`. It
+    /// starts with the [`Display`] summary, whose occupancies are weights in a weighted
+    /// cache, and adds the numbers of entries, the configuration and the features:
+    /// `weighted` for a weighted cache and the `ttl` and `stats` features of the crate
+    /// that are compiled in.
+    ///
+    /// Intended for log lines and error contexts
+    pub fn describe(&self) -> String {
+        let mut features = Vec::new();
+        if self.weigher.is_some() {
+            features.push("weighted");
+        }
+        #[cfg(feature = "ttl")]
+        features.push("ttl");
+        #[cfg(feature = "stats")]
+        features.push("stats");
+        format!(
+            "{self} {{ small_len: {}, main_len: {}, ghost_capacity: {}, max_freq: {}, features: [{}] }}",
+            self.small_fifo.len(),
+            self.main_fifo.len(),
+            self.ghost_fifo.capacity(),
+            self.config.max_freq,
+            features.join(", ")
+        )
+    }
+}
+
+/// Destination of the entries evicted by the S3FIFO policy
+trait EvictionSink<K, V> {
+    /// Take the evicted entry, `listener` is the eviction listener of the cache
+    fn evicted(&mut self, listener: &mut Option<EvictionListener<K, V>>, k: K, v: V);
+}
+
+/// Eviction sink that passes the evicted entries to the eviction listener, or drops
+/// them if there is no listener
+struct NotifyListener;
+
+impl<K, V> EvictionSink<K, V> for NotifyListener {
+    #[inline]
+    fn evicted(&mut self, listener: &mut Option<EvictionListener<K, V>>, k: K, v: V) {
+        if let Some(listener) = listener {
+            listener(k, v);
+        }
+    }
+}
+
+/// Closures collect the evicted entries themselves, bypassing the listener
+impl<K, V, F> EvictionSink<K, V> for F
+where
+    F: FnMut(K, V),
+{
+    #[inline]
+    fn evicted(&mut self, _listener: &mut Option<EvictionListener<K, V>>, k: K, v: V) {
+        self(k, v)
+    }
+}
+
+/// Weight of the entry computed by `weigher`, 1 without a weigher
+#[inline]
+fn weight_of<K, V>(weigher: &Option<Weigher<K, V>>, k: &K, v: &V) -> usize {
+    weigher.as_ref().map_or(1, |weigher| weigher(k, v))
+}
+
+/// Capacity of a `HashTable` that holds at most `cap` entries under constant churn.
+///
+/// Removing from a `HashTable` may leave a tombstone that still consumes the growth
+/// budget. When the budget is exhausted, the table is rehashed in place if it is at most
+/// half full, otherwise it is reallocated with twice the buckets. A
+/// cache keeps inserting and removing keys at its capacity, so a table created with
+/// exactly `cap` would reallocate as soon as enough tombstones pile up. Doubling the
+/// capacity guarantees that the rehash is always performed in place
+#[inline]
+fn table_capacity(cap: usize) -> usize {
+    cap.saturating_mul(2)
+}
+
+/// Remove the index of the bucket from the table. Only the index is compared, the
+/// bucket is not read
+fn unlink(table: &mut HashTable<usize>, hash: HashValue, index: usize) {
+    match table.find_entry(hash, |&probe| probe == index) {
+        Ok(entry) => {
+            entry.remove();
+        }
+        Err(_) => unreachable!("Bucket in FIFO must in table"),
+    }
+}
+
+/// Draining iterator returned by [`S3FIFO::drain`]. The buckets that are not yielded
+/// are dropped with it
+struct Drain<'a, K, V> {
+    /// Takes the buckets out of the arena of the cache, which holds an empty one
+    /// meanwhile
+    inner: IntoIter<K, V>,
+    /// Arena of the cache, the drained arena is put back once it is cleared to keep its
+    /// allocation
+    arena: &'a mut Slab<Bucket<K, V>>,
+}
+
+impl<K, V> Iterator for Drain<'_, K, V> {
+    type Item = (K, V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V> Drop for Drain<'_, K, V> {
+    fn drop(&mut self) {
+        self.inner.buckets.clear();
+        mem::swap(self.arena, &mut self.inner.buckets);
+    }
+}
+
+/// FIFO that holds an entry, see [`S3FIFO::debug_entries`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fifo {
+    /// The small FIFO, where the new keys enter
+    Small,
+    /// The main FIFO, where the keys promoted from the small FIFO and the keys
+    /// remembered by the ghost FIFO enter
+    Main,
+}
+
+/// An iterator over the entries of a [`S3FIFO`], created by [`S3FIFO::iter`]
+pub struct Iter<'a, K, V> {
+    indices: FIFOIndices<'a, K, V>,
+    buckets: &'a Slab<Bucket<K, V>>,
+}
+
+type FIFOIndices<'a, K, V> = Chain<fifo::Indices<'a, K, V>, fifo::Indices<'a, K, V>>;
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let bucket = &self.buckets[self.indices.next()?];
+        Some((&bucket.key, &bucket.value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+/// A mutable iterator over the entries of a [`S3FIFO`], created by
+/// [`S3FIFO::iter_mut`]
+pub struct IterMut<'a, K, V> {
+    inner: slab::IterMut<'a, Bucket<K, V>>,
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let bucket = self.inner.next()?;
+        Some((&bucket.key, &mut bucket.value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// An owning iterator over the entries of a [`S3FIFO`], in the order of the small FIFO
+/// followed by the main FIFO. The allocations of the cache are released when it is
+/// dropped
+pub struct IntoIter<K, V> {
+    /// Index of the next bucket to take
+    next: Option<usize>,
+    /// Index of the front bucket of the main FIFO, taken once the small FIFO is done
+    main_front: Option<usize>,
+    buckets: Slab<Bucket<K, V>>,
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = match self.next {
+            Some(index) => index,
+            None => {
+                self.next = self.main_front.take();
+                self.next?
+            }
+        };
+        let bucket = self.buckets.remove(index);
+        self.next = index_of(bucket.next);
+        Some((bucket.key, bucket.value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.buckets.len(), Some(self.buckets.len()))
+    }
+}
+
+impl<K, V, S> IntoIterator for S3FIFO<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            next: self.small_fifo.front(),
+            main_front: self.main_fifo.front(),
+            buckets: self.buckets,
+        }
+    }
+}
+
+/// Put the pairs in order like [`S3FIFO::put`], so a later pair overwrites an earlier
+/// one with the same key and the cache evicts as usual while being filled
+impl<K, V, S> Extend<(K, V)> for S3FIFO<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.put(k, v);
+        }
+    }
+}
+
+/// Build a cache whose capacity is the upper bound of the size hint of the iterator and
+/// load the pairs into it like [`S3FIFO::from_iter_with_capacity`], so every pair is
+/// kept. An iterator without an upper bound is collected first to count its pairs.
+///
+/// The upper bound overestimates the pairs of a filtered iterator and the keys of an
+/// iterator with duplicates, the cache then has spare capacity. Use
+/// `from_iter_with_capacity` to pick the capacity
+impl<K, V> FromIterator<(K, V)> for S3FIFO<K, V, DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        match iter.size_hint() {
+            (_, Some(upper)) => Self::from_iter_with_capacity(upper, iter),
+            (_, None) => {
+                let pairs: Vec<(K, V)> = iter.collect();
+                Self::from_iter_with_capacity(pairs.len(), pairs)
+            }
+        }
+    }
+}
+
+/// Create an empty cache with capacity [`DEFAULT_CAPACITY`] and the default settings.
+/// Use [`S3FIFO::from_config`] to override the capacity, the ratios or the maximum
+/// frequency, and chain the `with_*` methods to set the callbacks
+impl<K, V, S> Default for S3FIFO<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(DEFAULT_CAPACITY, S::default())
+    }
+}
+
+/// Deep copy of the cache: the entries keep their frequencies and their places in the
+/// FIFOs, and the ghost FIFO, the watermarks and the statistics are copied. The
+/// buckets are referred to by their indices in the arena, which stay valid in the copy.
+///
+/// The weigher and the clock are shared with the copy. The access callback, the
+/// eviction listener and the admission policy are not cloned, since they usually carry
+/// the state of a single cache, set them again on the copy if needed
+impl<K, V, S> Clone for S3FIFO<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            hash_builder: self.hash_builder.clone(),
+            buckets: self.buckets.clone(),
+            small_fifo: self.small_fifo,
+            main_fifo: self.main_fifo,
+            small_size: self.small_size,
+            main_size: self.main_size,
+            small_weight: self.small_weight,
+            main_weight: self.main_weight,
+            ghost_fifo: self.ghost_fifo.clone(),
+            table: self.table.clone(),
+            config: self.config,
+            on_access: None,
+            eviction_listener: None,
+            weigher: self.weigher.clone(),
+            admission: None,
+            high_watermark: self.high_watermark,
+            low_watermark: self.low_watermark,
+            #[cfg(feature = "stats")]
+            stats: self.stats,
+            #[cfg(feature = "ttl")]
+            ttl: self.ttl,
+            #[cfg(feature = "ttl")]
+            clock: Arc::clone(&self.clock),
+        }
+    }
+}
+
+/// Concise one-line summary of the occupancy, e.g.
+/// `S3FIFO(len=1234/10000, small=100/1000, main=1134/9000, ghost=4000)`. The occupancy is
+/// a weight if the cache is weighted. [`S3FIFO::describe`] extends it with the numbers
+/// of entries, the configuration and the features
+impl<K, V, S> Display for S3FIFO<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "S3FIFO(len={}/{}, small={}/{}, main={}/{}, ghost={})",
+            self.weight(),
+            self.capacity(),
+            self.small_weight,
+            self.small_capacity(),
+            self.main_weight,
+            self.main_capacity(),
+            self.ghost_fifo.len()
+        )
+    }
+}
+
+/// Error returned when no room can be made in the cache for a new entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityError;
+
+impl Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("no room can be made in the cache for the new entry")
+    }
+}
+
+impl core::error::Error for CapacityError {}
+
+/// Outcome of [`S3FIFO::put_checked`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PutResult<K, V> {
+    /// The key was absent and is now cached
+    Inserted,
+    /// The key was present, its value is replaced and the old one is returned
+    Replaced(V),
+    /// The pair can never fit in the FIFO that would hold it, because the FIFO is
+    /// lighter than the value or has zero capacity, or the admission policy turned the
+    /// new key away. The pair is returned and no entry of the cache is touched
+    Rejected(K, V),
+}
+
+/// An entry of the cache.
+///
+/// The hash of the key is not stored: it is only needed again when the bucket leaves
+/// the cache, to unlink it from the table and to record it in the ghost FIFO, so it is
+/// recomputed then. Storing it would cost 8 bytes for every entry to save one hash per
+/// eviction, which is cheap next to the cache misses of the eviction itself
+#[derive(Clone)]
+struct Bucket<K, V> {
+    /// Key
+    key: K,
+    /// Value
+    value: V,
+    /// Weight of the entry when it was inserted or its value last replaced, 1 if the
+    /// cache is not weighted. Removing the bucket subtracts it from the weight of its
+    /// FIFO without calling the weigher again
+    weight: usize,
+    /// Frequency, incremented on access and saturated at the maximum frequency of the
+    /// cache. Decremented each time the bucket reaches the front of its FIFO: the main
+    /// FIFO evicts the bucket once it reaches 0, while the small FIFO promotes it if it
+    /// is still at least the promotion threshold and evicts it otherwise
+    freq: u8,
+    /// Whether the bucket is in the main FIFO rather than the small FIFO
+    in_main: bool,
+    /// Index of the previous bucket in its FIFO, towards the front, `NIL` at the front
+    prev: u32,
+    /// Index of the next bucket in its FIFO, towards the back, `NIL` at the back
+    next: u32,
+    /// When the entry was put into the cache
+    #[cfg(feature = "ttl")]
+    inserted_at: Instant,
+    /// When the entry expires, `None` if it never does
+    #[cfg(feature = "ttl")]
+    deadline: Option<Instant>,
+}
+
+impl<K, V> Bucket<K, V> {
+    #[inline]
+    fn incr_freq(&mut self, max_freq: u8) {
+        self.freq = self.freq.saturating_add(1).min(max_freq);
+    }
+}
+
+// `repr(Rust)` already reorders the fields to minimize the padding, so the order of
+// the declaration does not matter. Pin the sizes to catch layout regressions, the
+// timestamps of the `ttl` feature add 32 bytes
+#[cfg(all(target_pointer_width = "64", not(feature = "ttl")))]
+const _: () = {
+    assert!(mem::size_of::<Bucket<u64, ()>>() == 32);
+    assert!(mem::size_of::<Bucket<u64, u32>>() == 32);
+};
+
+/// A ghost FIFO that only remembers the hashes of the keys evicted from the small FIFO.
+///
+/// Each hash is stored once, in the ring buffer. The table only holds the sequence
+/// number of each hash, counted from the first hash pushed since the last clear, which
+/// is half the size of a hash. A hash sits in the ring buffer at its sequence number
+/// minus the one of the front hash, the wrapping arithmetic keeps this exact as long as
+/// the ghost holds at most `u32::MAX` hashes
+#[derive(Clone)]
+struct GhostFIFOCache {
+    /// Sequence numbers of the hashes, keyed by the hashes
+    table: HashTable<u32>,
+    /// Hashes from the oldest to the newest, to evict them in FIFO order
+    ring_buffer: VecDeque<GhostHash>,
+    /// Sequence number of the front hash
+    head: u32,
+    /// Maximum number of hashes, clamped to `u32::MAX`
+    capacity: usize,
+}
+
+impl GhostFIFOCache {
+    /// Create the ghost with capacity `cap`, the memory is only allocated upfront if
+    /// `preallocate` is true
+    fn new(cap: usize, preallocate: bool) -> Self {
+        let cap = cap.min(u32::MAX as usize);
+        let alloc_cap = if preallocate { cap } else { 0 };
+        Self {
+            table: HashTable::with_capacity(table_capacity(alloc_cap)),
+            ring_buffer: VecDeque::with_capacity(alloc_cap),
+            head: 0,
+            capacity: cap,
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.ring_buffer.len()
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.table.clear();
+        self.ring_buffer.clear();
+        self.head = 0;
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Remove all of the hashes and resize the ghost to hold `cap` hashes, reusing the
+    /// allocations. The memory is only allocated upfront if `preallocate` is true
+    fn reset_capacity(&mut self, cap: usize, preallocate: bool) {
+        self.clear();
+        self.capacity = cap.min(u32::MAX as usize);
+        let alloc_cap = if preallocate { self.capacity } else { 0 };
+        if alloc_cap > self.ring_buffer.capacity() {
+            self.ring_buffer.reserve_exact(alloc_cap);
+        } else {
+            self.ring_buffer.shrink_to(alloc_cap);
+        }
+
+        // The table is empty, the hasher is never called
+        let table_cap = table_capacity(alloc_cap);
+        if table_cap > self.table.capacity() {
+            self.table.reserve(table_cap, |_| unreachable!());
+        } else {
+            self.table.shrink_to(table_cap, |_| unreachable!());
+        }
+    }
+
+    /// Change the capacity to `cap` while keeping the newest hashes that fit. The memory
+    /// is only reserved upfront if `preallocate` is true, and never shrinks
+    fn resize(&mut self, cap: usize, preallocate: bool) {
+        let cap = cap.min(u32::MAX as usize);
+        while self.ring_buffer.len() > cap {
+            self.pop_front();
+        }
+        self.capacity = cap;
+        if preallocate {
+            self.ring_buffer.reserve_exact(cap - self.ring_buffer.len());
+            let (ring_buffer, head) = (&self.ring_buffer, self.head);
+            self.table.reserve(
+                table_capacity(cap).saturating_sub(self.table.len()),
+                |&seq| table_hash(hash_at(ring_buffer, head, seq)),
+            );
+        }
+    }
+
+    /// Size of the allocations of the ring buffer and the table in bytes
+    fn allocation_size(&self) -> usize {
+        self.ring_buffer.capacity() * mem::size_of::<GhostHash>() + self.table.allocation_size()
+    }
+
+    /// Release the memory allocated beyond the hashes it holds
+    fn shrink_to_fit(&mut self) {
+        self.ring_buffer.shrink_to_fit();
+        let (ring_buffer, head) = (&self.ring_buffer, self.head);
+        self.table
+            .shrink_to(table_capacity(ring_buffer.len()), |&seq| {
+                table_hash(hash_at(ring_buffer, head, seq))
+            });
+    }
+
+    #[inline]
+    fn contains(&self, hash: HashValue) -> bool {
+        self.contains_ghost_hash(ghost_hash(hash))
+    }
+
+    #[inline]
+    fn contains_ghost_hash(&self, hash: GhostHash) -> bool {
+        self.table
+            .find(table_hash(hash), |&seq| {
+                hash_at(&self.ring_buffer, self.head, seq) == hash
+            })
+            .is_some()
+    }
+
+    /// Returns true if a hash can be remembered without growing the ring buffer or the
+    /// table. A full ghost drops its oldest hash first, which frees a slot of the ring
+    /// buffer but not necessarily of the table
+    #[inline]
+    fn insert_fits(&self) -> bool {
+        let len = self.ring_buffer.len();
+        self.capacity == 0
+            || (self.table.len() < self.table.capacity()
+                && (len < self.ring_buffer.capacity() || len == self.capacity))
+    }
+
+    /// Remember the hash, returns true if the oldest hash was dropped to make room
+    #[inline]
+    fn insert(&mut self, hash: HashValue) -> bool {
+        self.insert_ghost_hash(ghost_hash(hash))
+    }
+
+    /// Remember the hash as stored in the ring buffer, see [`insert`](Self::insert)
+    fn insert_ghost_hash(&mut self, hash: GhostHash) -> bool {
+        if self.capacity == 0 || self.contains_ghost_hash(hash) {
+            return false;
+        }
+
+        let full = self.ring_buffer.len() == self.capacity;
+        if full {
+            self.pop_front();
+        }
+
+        debug_assert!(self.ring_buffer.len() < self.capacity);
+        let seq = self.head.wrapping_add(self.ring_buffer.len() as u32);
+        self.ring_buffer.push_back(hash);
+
+        let (ring_buffer, head) = (&self.ring_buffer, self.head);
+        self.table.insert_unique(table_hash(hash), seq, |&seq| {
+            table_hash(hash_at(ring_buffer, head, seq))
+        });
+        full
+    }
+
+    /// Forget the oldest hash
+    #[inline]
+    fn pop_front(&mut self) {
+        if let Some(garbage_hash) = self.ring_buffer.pop_front() {
+            let head = self.head;
+            let entry = self
+                .table
+                .find_entry(table_hash(garbage_hash), |&seq| seq == head)
+                .unwrap();
+            entry.remove();
+            self.head = head.wrapping_add(1);
+        }
+    }
+}
+
+/// Hash with sequence number `seq` in the ring buffer whose front hash has sequence
+/// number `head`
+#[inline]
+fn hash_at(ring_buffer: &VecDeque<GhostHash>, head: u32, seq: u32) -> GhostHash {
+    ring_buffer[seq.wrapping_sub(head) as usize]
+}
+
+/// Hash of a key as remembered by the ghost FIFO
+#[cfg(not(feature = "hash32"))]
+#[inline]
+fn ghost_hash(hash: HashValue) -> GhostHash {
+    hash
+}
+
+/// Hash of a key as remembered by the ghost FIFO, its low half. The sharded cache picks
+/// the shard from the high half, so the hashes of the keys of a shard still differ in
+/// their low half
+#[cfg(feature = "hash32")]
+#[inline]
+fn ghost_hash(hash: HashValue) -> GhostHash {
+    hash as GhostHash
+}
+
+/// Hash of a remembered hash in the table of the ghost FIFO
+#[cfg(not(feature = "hash32"))]
+#[inline]
+fn table_hash(hash: GhostHash) -> HashValue {
+    hash
+}
+
+/// Hash of a remembered hash in the table of the ghost FIFO. The table picks the group
+/// from the low bits and the tag from the 7 high bits of the hash, so the 32 bits are
+/// spread over the 64 bits by a multiplication with an odd constant, which keeps the
+/// low bits distinct rather than giving every hash the same tag
+#[cfg(feature = "hash32")]
+#[inline]
+fn table_hash(hash: GhostHash) -> HashValue {
+    HashValue::from(hash).wrapping_mul(0x9e37_79b9_7f4a_7c15)
 }
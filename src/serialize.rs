@@ -0,0 +1,121 @@
+//! Serde support of the cache, compiled only with the `serde` feature
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::hash::{BuildHasher, Hash};
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::fifo::LinkedFIFO;
+use crate::slab::Slab;
+use crate::{Bucket, Config, GhostHash, S3FIFO};
+
+/// Layout of a serialized cache. The buckets of each FIFO are `(key, value, freq)`
+/// tuples from front to back, and the ghost FIFO is its hashes from front to back
+#[derive(Serialize)]
+#[serde(rename = "S3FIFO")]
+struct SerializeCache<'a, K, V> {
+    config: &'a Config,
+    small: SerializeFIFO<'a, K, V>,
+    main: SerializeFIFO<'a, K, V>,
+    ghost: &'a VecDeque<GhostHash>,
+}
+
+/// The buckets of a FIFO, serialized in place without collecting them
+struct SerializeFIFO<'a, K, V> {
+    fifo: &'a LinkedFIFO,
+    buckets: &'a Slab<Bucket<K, V>>,
+}
+
+impl<K: Serialize, V: Serialize> Serialize for SerializeFIFO<'_, K, V> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        serializer.collect_seq(self.fifo.iter(self.buckets).map(|index| {
+            let bucket = &self.buckets[index];
+            (&bucket.key, &bucket.value, bucket.freq)
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "S3FIFO")]
+struct DeserializeCache<K, V> {
+    config: Config,
+    small: Vec<(K, V, u8)>,
+    main: Vec<(K, V, u8)>,
+    ghost: Vec<GhostHash>,
+}
+
+/// Serialize the configuration, the live entries with their frequencies in FIFO order
+/// and the hashes of the ghost FIFO.
+///
+/// The weigher, the callbacks and the statistics are not serialized, and neither are
+/// the deadlines of the `ttl` feature since an `Instant` is meaningless in another
+/// process
+impl<K, V, S> Serialize for S3FIFO<K, V, S>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        SerializeCache {
+            config: &self.config,
+            small: SerializeFIFO {
+                fifo: &self.small_fifo,
+                buckets: &self.buckets,
+            },
+            main: SerializeFIFO {
+                fifo: &self.main_fifo,
+                buckets: &self.buckets,
+            },
+            ghost: &self.ghost_fifo.ring_buffer,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Rebuild an equivalent unweighted cache with the default hash builder `S`: the
+/// entries are pushed back into their FIFOs in order with their frequencies, and the
+/// table is rebuilt from the keys.
+///
+/// The ghost FIFO stores hashes rather than keys, so it is only meaningful if `S`
+/// produces the same hashes as the hash builder of the serialized cache. Randomly
+/// seeded hash builders, like the default one, do not. An invalid configuration, a
+/// duplicated key or a FIFO longer than its capacity is an error.
+///
+/// Only the memory for the restored entries and hashes is allocated, the cache grows
+/// with the next insertions like after [`shrink_to_fit`](S3FIFO::shrink_to_fit), so
+/// the capacity of a corrupted input can not exhaust the memory
+impl<'de, K, V, S> Deserialize<'de> for S3FIFO<K, V, S>
+where
+    K: Deserialize<'de> + Eq + Hash,
+    V: Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let DeserializeCache {
+            config,
+            small,
+            main,
+            ghost,
+        } = DeserializeCache::deserialize(deserializer)?;
+        let mut cache =
+            Self::from_untrusted_config(config, S::default()).map_err(D::Error::custom)?;
+        if ghost.len() > cache.ghost_fifo.capacity() {
+            return Err(D::Error::custom("ghost FIFO length exceeds its capacity"));
+        }
+
+        for (in_main, entries) in [(false, small), (true, main)] {
+            for (key, value, freq) in entries {
+                cache
+                    .restore_bucket(key, value, freq, in_main)
+                    .map_err(D::Error::custom)?;
+            }
+        }
+        for hash in ghost {
+            cache.ghost_fifo.insert_ghost_hash(hash);
+        }
+
+        Ok(cache)
+    }
+}
@@ -0,0 +1,182 @@
+//! Arena that owns the buckets of the cache
+
+use alloc::vec::Vec;
+use core::mem;
+use core::ops::{Index, IndexMut};
+use core::slice;
+
+/// An arena of values addressed by index. The index of a value is stable until it is
+/// removed, regardless of how many values are inserted or removed around it, so the
+/// FIFOs and the table can refer to the buckets by index instead of by address.
+///
+/// The slots of the removed values are linked into a free list and reused by the next
+/// insertions, therefore the arena never holds more slots than the maximum number of
+/// values it held at once
+#[derive(Clone)]
+pub(crate) struct Slab<T> {
+    slots: Vec<Slot<T>>,
+    /// Head of the free list, `slots.len()` if the list is empty
+    next_vacant: usize,
+    len: usize,
+}
+
+#[derive(Clone)]
+enum Slot<T> {
+    Occupied(T),
+    /// Vacant slot, holds the index of the next vacant slot in the free list
+    Vacant(usize),
+}
+
+impl<T> Slab<T> {
+    /// Size of a slot holding one value
+    pub(crate) const SLOT_SIZE: usize = mem::size_of::<Slot<T>>();
+
+    pub(crate) fn with_capacity(cap: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(cap),
+            next_vacant: 0,
+            len: 0,
+        }
+    }
+
+    /// Number of values in the arena
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the slot at `index` holds a value
+    #[cfg(debug_assertions)]
+    #[inline]
+    pub(crate) fn contains(&self, index: usize) -> bool {
+        matches!(self.slots.get(index), Some(Slot::Occupied(_)))
+    }
+
+    /// Returns true if a value can be inserted without growing the arena
+    #[inline]
+    pub(crate) fn has_vacancy(&self) -> bool {
+        self.next_vacant < self.slots.len() || self.slots.len() < self.slots.capacity()
+    }
+
+    /// Insert the value into a vacant slot and return its index
+    #[inline]
+    pub(crate) fn insert(&mut self, value: T) -> usize {
+        let index = self.next_vacant;
+        if index == self.slots.len() {
+            self.slots.push(Slot::Occupied(value));
+            self.next_vacant = self.slots.len();
+        } else {
+            match mem::replace(&mut self.slots[index], Slot::Occupied(value)) {
+                Slot::Vacant(next) => self.next_vacant = next,
+                Slot::Occupied(_) => unreachable!("Slot in the free list must be vacant"),
+            }
+        }
+        self.len += 1;
+        index
+    }
+
+    /// Remove the value at `index` and return it
+    ///
+    /// # Panics
+    ///
+    /// Panics if the slot at `index` is vacant
+    #[inline]
+    pub(crate) fn remove(&mut self, index: usize) -> T {
+        match mem::replace(&mut self.slots[index], Slot::Vacant(self.next_vacant)) {
+            Slot::Occupied(value) => {
+                self.next_vacant = index;
+                self.len -= 1;
+                value
+            }
+            Slot::Vacant(next) => {
+                self.slots[index] = Slot::Vacant(next);
+                panic!("Remove the vacant slot {index}")
+            }
+        }
+    }
+
+    /// Remove all of the values, keeping the allocated capacity
+    pub(crate) fn clear(&mut self) {
+        self.slots.clear();
+        self.next_vacant = 0;
+        self.len = 0;
+    }
+
+    /// Iterate over the mutable references to the values, in the order of their slots
+    pub(crate) fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            slots: self.slots.iter_mut(),
+            len: self.len,
+        }
+    }
+
+    /// Size of the allocation of the slots in bytes
+    pub(crate) fn allocation_size(&self) -> usize {
+        self.slots.capacity() * Self::SLOT_SIZE
+    }
+
+    /// Reserve room for `cap` values in total, never shrinks the arena
+    pub(crate) fn reserve(&mut self, cap: usize) {
+        self.slots
+            .reserve_exact(cap.saturating_sub(self.slots.len()));
+    }
+
+    /// Remove all of the values and resize the arena to hold exactly `cap` values
+    pub(crate) fn reset_capacity(&mut self, cap: usize) {
+        self.clear();
+        if cap > self.slots.capacity() {
+            self.slots.reserve_exact(cap);
+        } else {
+            self.slots.shrink_to(cap);
+        }
+    }
+}
+
+/// Iterator returned by [`Slab::iter_mut`]
+pub(crate) struct IterMut<'a, T> {
+    slots: slice::IterMut<'a, Slot<T>>,
+    /// Number of values not yielded yet
+    len: usize,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        for slot in &mut self.slots {
+            if let Slot::Occupied(value) = slot {
+                self.len -= 1;
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T> Index<usize> for Slab<T> {
+    type Output = T;
+
+    #[inline]
+    fn index(&self, index: usize) -> &T {
+        match &self.slots[index] {
+            Slot::Occupied(value) => value,
+            Slot::Vacant(_) => panic!("Access the vacant slot {index}"),
+        }
+    }
+}
+
+impl<T> IndexMut<usize> for Slab<T> {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        match &mut self.slots[index] {
+            Slot::Occupied(value) => value,
+            Slot::Vacant(_) => panic!("Access the vacant slot {index}"),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Compact binary snapshot of the cache, used to warm up a cache after restart
+//! without depending on serde
+
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Read, Write};
+use std::mem;
+
+use crate::{Config, GhostHash, S3FIFO};
+
+/// Encode and decode a key or a value of the snapshot
+pub trait ByteCodec: Sized {
+    /// Append the encoded bytes of `self` to `buf`
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Decode the bytes produced by [`encode`](ByteCodec::encode)
+    fn decode(bytes: &[u8]) -> io::Result<Self>;
+}
+
+macro_rules! impl_byte_codec_for_integers {
+    ($($ty:ty),*) => {
+        $(
+            impl ByteCodec for $ty {
+                #[inline]
+                fn encode(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+
+                #[inline]
+                fn decode(bytes: &[u8]) -> io::Result<Self> {
+                    bytes
+                        .try_into()
+                        .map(<$ty>::from_le_bytes)
+                        .map_err(|_| invalid_data(concat!("invalid length of ", stringify!($ty))))
+                }
+            }
+        )*
+    };
+}
+
+impl_byte_codec_for_integers!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+impl ByteCodec for () {
+    #[inline]
+    fn encode(&self, _buf: &mut Vec<u8>) {}
+
+    #[inline]
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid_data("invalid length of ()"))
+        }
+    }
+}
+
+impl ByteCodec for bool {
+    #[inline]
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+
+    #[inline]
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        match bytes {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(invalid_data("invalid bool")),
+        }
+    }
+}
+
+impl ByteCodec for Vec<u8> {
+    #[inline]
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    #[inline]
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl ByteCodec for String {
+    #[inline]
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    #[inline]
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid_data("invalid UTF-8 string"))
+    }
+}
+
+impl<K, V, S> S3FIFO<K, V, S>
+where
+    K: Eq + Hash + ByteCodec,
+    V: ByteCodec,
+    S: BuildHasher,
+{
+    /// Write the snapshot of the cache into `w`.
+    ///
+    /// The snapshot is a sequence of little endian integers:
+    ///
+    /// - the configuration: capacity as `u64`, small ratio and ghost ratio as `f64`,
+    ///   maximum ghost size as `u64`, maximum frequency as `u8`, whether the accesses
+    ///   bump the frequencies in the small FIFO as `u8`, promotion threshold as `u8`
+    /// - number of buckets in the small FIFO as `u64`, followed by the buckets
+    /// - number of buckets in the main FIFO as `u64`, followed by the buckets
+    /// - number of hashes in the ghost FIFO as `u64`, followed by the hashes as `u64`,
+    ///   or as `u32` with the `hash32` feature. A snapshot is only readable by a build
+    ///   with the same choice of the feature
+    ///
+    /// Each bucket is its frequency as `u8`, followed by the length of the encoded key
+    /// as `u64`, the encoded key, the length of the encoded value as `u64` and the
+    /// encoded value. Buckets and hashes are written in FIFO order, from front to back
+    pub fn write_snapshot(&self, mut w: impl Write) -> io::Result<()> {
+        write_u64(&mut w, self.config.capacity)?;
+        w.write_all(&self.config.small_ratio.to_le_bytes())?;
+        w.write_all(&self.config.ghost_ratio.to_le_bytes())?;
+        write_u64(&mut w, self.config.max_ghost_size)?;
+        w.write_all(&[self.config.max_freq])?;
+        w.write_all(&[u8::from(self.config.bump_small_on_get)])?;
+        w.write_all(&[self.config.promotion_threshold])?;
+
+        let mut buf = Vec::new();
+        for fifo in [&self.small_fifo, &self.main_fifo] {
+            write_u64(&mut w, fifo.len())?;
+            for index in fifo.iter(&self.buckets) {
+                let bucket = &self.buckets[index];
+                w.write_all(&[bucket.freq])?;
+                buf.clear();
+                bucket.key.encode(&mut buf);
+                write_u64(&mut w, buf.len())?;
+                w.write_all(&buf)?;
+                buf.clear();
+                bucket.value.encode(&mut buf);
+                write_u64(&mut w, buf.len())?;
+                w.write_all(&buf)?;
+            }
+        }
+
+        write_u64(&mut w, self.ghost_fifo.len())?;
+        for &hash in &self.ghost_fifo.ring_buffer {
+            w.write_all(&hash.to_le_bytes())?;
+        }
+        w.flush()
+    }
+
+    /// Read a cache from the snapshot written by [`write_snapshot`].
+    ///
+    /// The table is rebuilt with `hash_builder`. The ghost FIFO stores hashes rather than
+    /// keys, so it is only meaningful if `hash_builder` produces the same hashes as the
+    /// hash builder of the cache that wrote the snapshot. Randomly seeded hash builders,
+    /// like the default one, do not.
+    ///
+    /// Only the memory for the restored entries and hashes is allocated, the cache grows
+    /// with the next insertions like after [`shrink_to_fit`](S3FIFO::shrink_to_fit).
+    /// The capacity read from a corrupted snapshot therefore can not exhaust the memory,
+    /// and the lengths that exceed the capacities are rejected as
+    /// [`InvalidData`](io::ErrorKind::InvalidData)
+    ///
+    /// [`write_snapshot`]: S3FIFO::write_snapshot
+    pub fn read_snapshot(mut r: impl Read, hash_builder: S) -> io::Result<Self> {
+        let config = Config {
+            capacity: read_u64(&mut r)?,
+            small_ratio: read_f64(&mut r)?,
+            ghost_ratio: read_f64(&mut r)?,
+            max_ghost_size: read_u64(&mut r)?,
+            max_freq: read_u8(&mut r)?,
+            bump_small_on_get: read_u8(&mut r)? != 0,
+            promotion_threshold: read_u8(&mut r)?,
+        };
+        let mut cache = Self::from_untrusted_config(config, hash_builder)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut buf = Vec::new();
+        for in_main in [false, true] {
+            let len = read_u64(&mut r)?;
+            let size = if in_main {
+                cache.main_size
+            } else {
+                cache.small_size
+            };
+            if len > size {
+                return Err(invalid_data("FIFO length exceeds its capacity"));
+            }
+
+            for _ in 0..len {
+                let freq = read_u8(&mut r)?;
+                let key = K::decode(read_bytes(&mut r, &mut buf)?)?;
+                let value = V::decode(read_bytes(&mut r, &mut buf)?)?;
+                cache
+                    .restore_bucket(key, value, freq, in_main)
+                    .map_err(invalid_data)?;
+            }
+        }
+
+        let len = read_u64(&mut r)?;
+        if len > cache.ghost_fifo.capacity() {
+            return Err(invalid_data("ghost FIFO length exceeds its capacity"));
+        }
+        for _ in 0..len {
+            let mut bytes = [0; mem::size_of::<GhostHash>()];
+            r.read_exact(&mut bytes)?;
+            cache
+                .ghost_fifo
+                .insert_ghost_hash(GhostHash::from_le_bytes(bytes));
+        }
+
+        Ok(cache)
+    }
+}
+
+#[inline]
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[inline]
+fn write_u64(w: &mut impl Write, v: usize) -> io::Result<()> {
+    w.write_all(&(v as u64).to_le_bytes())
+}
+
+#[inline]
+fn read_u64(r: &mut impl Read) -> io::Result<usize> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    usize::try_from(u64::from_le_bytes(bytes)).map_err(|_| invalid_data("length overflows usize"))
+}
+
+#[inline]
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut byte = 0;
+    r.read_exact(std::slice::from_mut(&mut byte))?;
+    Ok(byte)
+}
+
+#[inline]
+fn read_f64(r: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+/// Read the length prefixed bytes into `buf`
+#[inline]
+fn read_bytes<'a>(r: &mut impl Read, buf: &'a mut Vec<u8>) -> io::Result<&'a [u8]> {
+    let len = read_u64(r)?;
+    buf.clear();
+    // Do not trust the length to allocate, it may come from a corrupted snapshot
+    r.take(len as u64).read_to_end(buf)?;
+    if buf.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
@@ -0,0 +1,71 @@
+//! Runtime statistics of the cache, compiled only with the `stats` feature
+
+/// Weight of the latest lookup in [`CacheStats::recent_hit_ratio`]. The moving average
+/// roughly covers the last 1000 lookups
+const RECENT_WEIGHT: f64 = 1.0 / 1000.0;
+
+/// Counters of the events in a [`S3FIFO`](crate::S3FIFO), returned by
+/// [`S3FIFO::stats`](crate::S3FIFO::stats).
+///
+/// The lookups are the calls that count as an access, like `get`, `get_mut` and the
+/// `get_or_insert` family. `peek` and `contains_key` only inspect the cache and are not
+/// counted
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    /// Lookups that found the key
+    pub hits: u64,
+    /// Lookups that did not find the key
+    pub misses: u64,
+    /// Entries inserted into the cache
+    pub insertions: u64,
+    /// Insertions admitted to the main FIFO because the ghost FIFO remembered the key
+    pub ghost_hits: u64,
+    /// Entries evicted from the small FIFO
+    pub evictions_from_small: u64,
+    /// Entries promoted from the small FIFO to the main FIFO
+    pub promotions_to_main: u64,
+    /// Entries evicted from the main FIFO
+    pub evictions_from_main: u64,
+    /// Hashes dropped by the ghost FIFO to make room for a newer one
+    pub ghost_evictions: u64,
+    /// New keys turned away by the admission policy on `put`
+    pub admission_rejections: u64,
+    /// Reallocations of the table that indexes the entries. The table is sized such
+    /// that this never happens, a non-zero count means it was outgrown
+    pub table_reallocations: u64,
+    /// Changes of the sizes of the FIFOs, by `resize`, `set_small_ratio` or
+    /// `reset_to_capacity`
+    pub resizes: u64,
+    /// Exponential moving average of the hit ratio over roughly the last 1000 lookups,
+    /// reacts to the shifts of the workload that the cumulative ratio hides
+    pub recent_hit_ratio: f64,
+}
+
+impl CacheStats {
+    /// Cumulative ratio of the lookups that found the key, 0 if nothing was looked up
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+
+    /// Record the outcome of a lookup
+    #[inline]
+    pub(crate) fn record_lookup(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        let sample = if hit { 1.0 } else { 0.0 };
+        if self.hits + self.misses == 1 {
+            // Start from the first sample rather than biasing the average towards 0
+            self.recent_hit_ratio = sample;
+        } else {
+            self.recent_hit_ratio += (sample - self.recent_hit_ratio) * RECENT_WEIGHT;
+        }
+    }
+}
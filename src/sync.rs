@@ -0,0 +1,320 @@
+//! Thread safe wrapper of the cache
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, LockResult, Mutex, MutexGuard};
+
+use hashbrown::DefaultHashBuilder;
+
+use crate::{HashValue, NotifyListener, S3FIFO};
+
+/// A thread safe [`S3FIFO`] that guards the cache with a single `Mutex`.
+///
+/// Every operation, including `get`, mutates the cache because it bumps the frequency,
+/// so a `RwLock` would not let the readers run in parallel and the cache takes an
+/// exclusive lock for everything. References can not outlive the guard, therefore
+/// [`get`](SyncS3FIFO::get) returns a clone of the value. Cheaply cloneable values like
+/// `Arc<T>` suit it best.
+///
+/// The single lock serializes all of the threads. The critical sections are short, so
+/// it is fine with a few threads, but the throughput stops scaling once they contend on
+/// the lock. Splitting the keys over independent shards, each with its own lock,
+/// scales further at the cost of evicting within a shard rather than over the whole
+/// cache.
+///
+/// A panic while the lock is held, for example in the hasher or the weigher, poisons
+/// the cache like any `Mutex`, since it may interrupt an update halfway. The operations
+/// of the wrapper then panic in every thread. [`lock`](SyncS3FIFO::lock) still hands out
+/// the guard of a poisoned cache, such that it can be cleared and the poisoning cleared
+/// with [`clear_poison`](SyncS3FIFO::clear_poison)
+pub struct SyncS3FIFO<K, V, S = DefaultHashBuilder> {
+    inner: Mutex<S3FIFO<K, V, S>>,
+}
+
+impl<K, V> SyncS3FIFO<K, V, DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    /// Create a new `SyncS3FIFO` with capacity `cap`, see [`S3FIFO::new`]
+    pub fn new(cap: usize) -> Self {
+        Self::from(S3FIFO::new(cap))
+    }
+}
+
+impl<K, V, S> SyncS3FIFO<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Get a clone of the value with given key and bump its frequency
+    pub fn get<Q>(&self, k: &Q) -> Option<V>
+    where
+        V: Clone,
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        lock(&self.inner).get(k).cloned()
+    }
+
+    /// Put the key-value pair into the cache, returns the old value if the key is
+    /// present. See [`S3FIFO::put`]
+    pub fn put(&self, k: K, v: V) -> Option<V> {
+        lock(&self.inner).put(k, v)
+    }
+
+    /// Remove the entry with given key and return its value, see [`S3FIFO::remove`]
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        lock(&self.inner).remove(k)
+    }
+
+    /// Returns true if the cache holds the key, without bumping its frequency
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        lock(&self.inner).contains_key(k)
+    }
+
+    /// Copy the live entries into an immutable map under a single lock. Readers can
+    /// consult the map without locking until the next refresh, which suits the
+    /// read-heavy paths that tolerate stale values.
+    ///
+    /// The map is a point in time copy with no freshness guarantee: it misses the later
+    /// writes and keeps the entries evicted or removed since. Reading it bumps no
+    /// frequency, so the keys only read from the map are not protected from eviction.
+    /// The ghost FIFO holds no value and is not copied. With the `ttl` feature, the
+    /// expired entries are removed from the cache first. Every call clones all of the
+    /// entries, so refresh the map periodically rather than on every read
+    pub fn snapshot(&self) -> Arc<HashMap<K, V>>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut guard = lock(&self.inner);
+        let cache = &mut *guard;
+        #[cfg(feature = "ttl")]
+        cache.cleanup_expired();
+        let map = cache.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        Arc::new(map)
+    }
+}
+
+impl<K, V, S> SyncS3FIFO<K, V, S> {
+    /// Number of entries in the cache
+    pub fn len(&self) -> usize {
+        lock(&self.inner).len()
+    }
+
+    /// Returns true if the cache holds no entry
+    pub fn is_empty(&self) -> bool {
+        lock(&self.inner).is_empty()
+    }
+
+    /// Capacity requested when the cache was created, see [`S3FIFO::capacity`]
+    pub fn capacity(&self) -> usize {
+        lock(&self.inner).capacity()
+    }
+
+    /// Remove all of the entries, see [`S3FIFO::clear`]
+    pub fn clear(&self) {
+        lock(&self.inner).clear();
+    }
+
+    /// Lock the cache for the operations this wrapper does not expose, or for several
+    /// operations that must not interleave with the other threads.
+    ///
+    /// Returns an error holding the guard if the cache is poisoned, like
+    /// [`Mutex::lock`]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, S3FIFO<K, V, S>>> {
+        self.inner.lock()
+    }
+
+    /// Returns true if a thread panicked while holding the lock
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    /// Clear the poisoning, once the cache is known to be consistent again, for example
+    /// after clearing it through [`lock`](SyncS3FIFO::lock)
+    pub fn clear_poison(&self) {
+        self.inner.clear_poison();
+    }
+
+    /// Consume the wrapper and return the cache, or an error holding it if the cache is
+    /// poisoned
+    // The error holds the cache by value, like the one of `Mutex::into_inner`
+    #[allow(clippy::result_large_err)]
+    pub fn into_inner(self) -> LockResult<S3FIFO<K, V, S>> {
+        self.inner.into_inner()
+    }
+}
+
+impl<K, V, S> From<S3FIFO<K, V, S>> for SyncS3FIFO<K, V, S> {
+    fn from(cache: S3FIFO<K, V, S>) -> Self {
+        Self {
+            inner: Mutex::new(cache),
+        }
+    }
+}
+
+/// A thread safe cache that splits the keys over independent [`S3FIFO`] shards, each
+/// behind its own `Mutex`.
+///
+/// Threads only contend when they touch keys of the same shard, so the throughput keeps
+/// scaling with the threads where a single lock like [`SyncS3FIFO`] does not. The price
+/// is that each shard evicts on its own: a shard that receives more hot keys than its
+/// share of the capacity evicts them while the other shards have room, so the hit ratio
+/// is slightly below the one of a single cache with the same total capacity.
+///
+/// The key is hashed once. The shard is picked from the bits 32 to 63 of the hash, while
+/// the table of the shard uses the low bits to pick a bucket, so the keys of a shard
+/// still spread over its table.
+///
+/// A panic while a shard is locked poisons that shard like in [`SyncS3FIFO`], and the
+/// operations that lock it panic from then on
+pub struct ShardedS3FIFO<K, V, S = DefaultHashBuilder> {
+    shards: Box<[Mutex<S3FIFO<K, V, S>>]>,
+    hash_builder: S,
+}
+
+impl<K, V> ShardedS3FIFO<K, V, DefaultHashBuilder>
+where
+    K: Eq + Hash,
+{
+    /// Create a new `ShardedS3FIFO` with total capacity `cap` split over `shards` shards,
+    /// see [`with_hasher`](ShardedS3FIFO::with_hasher)
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is 0
+    pub fn new(cap: usize, shards: usize) -> Self {
+        Self::with_hasher(cap, shards, DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> ShardedS3FIFO<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    /// Create a new `ShardedS3FIFO` with total capacity `cap` split over `shards` shards
+    /// that share the hash builder. Each shard takes `cap / shards`, and the remainder
+    /// is spread over the first shards, so the capacities sum up to `cap`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is 0
+    pub fn with_hasher(cap: usize, shards: usize, hash_builder: S) -> Self {
+        assert!(shards > 0, "ShardedS3FIFO needs at least one shard");
+        let shards = (0..shards)
+            .map(|i| {
+                let shard_cap = cap / shards + usize::from(i < cap % shards);
+                Mutex::new(S3FIFO::with_hasher(shard_cap, hash_builder.clone()))
+            })
+            .collect();
+        Self {
+            shards,
+            hash_builder,
+        }
+    }
+}
+
+impl<K, V, S> ShardedS3FIFO<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Get a clone of the value with given key and bump its frequency
+    pub fn get<Q>(&self, k: &Q) -> Option<V>
+    where
+        V: Clone,
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        self.shard(hash).get_hashed(hash, k).cloned()
+    }
+
+    /// Put the key-value pair into its shard, returns the old value if the key is
+    /// present. See [`S3FIFO::put`]
+    pub fn put(&self, k: K, v: V) -> Option<V> {
+        let hash = self.hash_builder.hash_one(&k);
+        self.shard(hash).put_hashed(hash, k, v, &mut NotifyListener)
+    }
+
+    /// Remove the entry with given key and return its value, see [`S3FIFO::remove`]
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        self.shard(hash).remove_hashed(hash, k)
+    }
+
+    /// Returns true if the cache holds the key, without bumping its frequency
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hash_builder.hash_one(k);
+        self.shard(hash).find_live(hash, k).is_some()
+    }
+
+    /// Lock the shard that holds the key with given hash
+    #[inline]
+    fn shard(&self, hash: HashValue) -> MutexGuard<'_, S3FIFO<K, V, S>> {
+        let index = (hash >> 32) as usize % self.shards.len();
+        lock(&self.shards[index])
+    }
+}
+
+impl<K, V, S> ShardedS3FIFO<K, V, S> {
+    /// Number of entries in all of the shards. The shards are locked one after the
+    /// other, so the result may be stale when other threads are writing
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).len()).sum()
+    }
+
+    /// Returns true if no shard holds an entry
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| lock(shard).is_empty())
+    }
+
+    /// Total capacity of the shards
+    pub fn capacity(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).capacity()).sum()
+    }
+
+    /// Number of shards
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Remove all of the entries, shard by shard
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            lock(shard).clear();
+        }
+    }
+}
+
+/// Lock the cache
+///
+/// # Panics
+///
+/// Panics if the cache is poisoned, a thread panicked while holding the lock and may
+/// have left it half updated
+#[inline]
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .expect("the cache is poisoned by a panic in another thread")
+}
@@ -0,0 +1,51 @@
+//! Time source of the expiration, compiled only with the `ttl` feature
+
+use std::time::Instant;
+
+/// Source of the current time, used to stamp the entries and decide when they expire.
+/// The clones of a cache share its clock, so it must be `Sync`.
+///
+/// Any `Fn() -> Instant` is a clock, so a test can share a fake time with the cache and
+/// advance it by hand:
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use std::time::{Duration, Instant};
+///
+/// let now = Arc::new(Mutex::new(Instant::now()));
+/// let clock = Arc::clone(&now);
+/// let mut cache = s3fifo::S3FIFO::new(10)
+///     .with_ttl(Duration::from_secs(10))
+///     .with_clock(move || *clock.lock().unwrap());
+///
+/// cache.put(1, 1);
+/// *now.lock().unwrap() += Duration::from_secs(11);
+/// assert_eq!(cache.peek(&1), None);
+/// assert_eq!(cache.get(&1), None);
+/// assert!(cache.is_empty());
+/// ```
+pub trait Clock: Send + Sync {
+    /// The current time, must never go backwards
+    fn now(&self) -> Instant;
+}
+
+/// Clock that reads [`Instant::now`], the default of the cache
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> Instant + Send + Sync,
+{
+    #[inline]
+    fn now(&self) -> Instant {
+        self()
+    }
+}
@@ -0,0 +1,81 @@
+//! Tests of the admission policies
+
+use s3fifo::{Fifo, SampledAdmission, S3FIFO};
+
+fn sampled(probability: f64) -> S3FIFO<u32, u32> {
+    S3FIFO::new(1000).with_admission(SampledAdmission::new(probability))
+}
+
+#[test]
+fn sampled_admission_rejects_cold_keys() {
+    let mut cache = sampled(0.0);
+    for k in 0..50 {
+        assert_eq!(cache.put(k, k), None);
+        assert!(!cache.contains_key(&k));
+        assert!(cache.in_ghost(&k));
+    }
+    assert!(cache.is_empty());
+
+    // A rejected key that comes back is admitted straight into the main FIFO
+    for k in 0..50 {
+        cache.put(k, k);
+    }
+    assert_eq!(cache.len(), 50);
+    assert!(cache.debug_entries().all(|(_, _, fifo)| fifo == Fifo::Main));
+}
+
+#[test]
+fn sampled_admission_admits_about_its_probability() {
+    let mut cache = sampled(0.25);
+    let mut admitted = 0;
+    for k in 0..10_000 {
+        cache.put(k, k);
+        // An admitted key is at the back of the small FIFO until the next put
+        admitted += usize::from(cache.contains_key(&k));
+    }
+    assert!(
+        (2000..3000).contains(&admitted),
+        "{admitted} of 10000 admitted"
+    );
+
+    let mut cache = sampled(1.0);
+    for k in 0..100 {
+        cache.put(k, k);
+    }
+    assert_eq!(cache.len(), 100);
+    assert_eq!(cache.ghost_len(), 0);
+}
+
+/// Hit ratio of the hot keys 0 to 199, each read once per round, while a scan puts
+/// 400 keys that are never seen again between two rounds
+fn hot_hit_ratio(mut cache: S3FIFO<u32, u32>) -> f64 {
+    let (mut hits, mut reads) = (0, 0);
+    let mut scan = 1_000_000;
+    for _ in 0..50 {
+        for k in 0..200 {
+            reads += 1;
+            if cache.get(&k).is_some() {
+                hits += 1;
+            } else {
+                cache.put(k, k);
+            }
+        }
+        for _ in 0..400 {
+            cache.put(scan, scan);
+            scan += 1;
+        }
+    }
+    hits as f64 / reads as f64
+}
+
+#[test]
+fn sampled_admission_resists_a_scan() {
+    // Every hot key is evicted by the scan before it is read again, and the ghost FIFO
+    // forgets it as well
+    let always = hot_hit_ratio(S3FIFO::new(300));
+    assert!(always < 0.05, "hit ratio {always} without admission policy");
+
+    // Most of the scan is turned away, the hot keys come back through the ghost FIFO
+    let sampled = hot_hit_ratio(S3FIFO::new(300).with_admission(SampledAdmission::new(0.1)));
+    assert!(sampled > 0.4, "hit ratio {sampled} with sampled admission");
+}
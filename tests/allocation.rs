@@ -0,0 +1,160 @@
+//! Tests of the allocations of the cache
+
+use std::panic;
+
+use s3fifo::{Config, DEFAULT_MAX_GHOST_SIZE, S3FIFO};
+
+#[test]
+fn filling_the_capacity_never_reallocates() {
+    for cap in [1, 7, 100, 1000, 4096] {
+        let mut cache = S3FIFO::new(cap);
+        let footprint = cache.memory_footprint();
+        // Twice the capacity of distinct keys, the accessed ones are promoted and the
+        // others evicted, which leaves tombstones in the table
+        for k in 0..2 * cap as u64 {
+            cache.put(k, k);
+            if k % 2 == 0 {
+                cache.get(&k);
+                cache.get(&k);
+            }
+        }
+        assert_eq!(cache.memory_footprint(), footprint, "capacity {cap}");
+        #[cfg(feature = "stats")]
+        assert_eq!(cache.stats().table_reallocations, 0, "capacity {cap}");
+    }
+}
+
+#[test]
+fn ghost_past_its_capacity_keeps_its_allocation() {
+    let mut cache = S3FIFO::new(100);
+    let footprint = cache.memory_footprint();
+    // Each key is evicted from the small FIFO into the ghost FIFO, which holds 90
+    for k in 0..10_000u64 {
+        cache.put(k, k);
+        assert!(cache.ghost_len() <= 90);
+    }
+    assert_eq!(cache.ghost_len(), 90);
+    assert_eq!(cache.memory_footprint(), footprint);
+}
+
+#[test]
+fn ghost_allocation_is_bounded_by_max_ghost_size() {
+    let footprint = |config: Config| {
+        S3FIFO::<u64, u64>::from_config(config, Default::default())
+            .unwrap()
+            .memory_footprint()
+    };
+    let config = Config::with_capacity(100_000);
+    let without_ghost = footprint(Config {
+        ghost_ratio: 0.0,
+        ..config
+    });
+    // The ghost FIFO of the main FIFO size remembers 90_000 hashes
+    let unbounded = footprint(config) - without_ghost;
+    let bounded = footprint(Config {
+        max_ghost_size: 1000,
+        ..config
+    }) - without_ghost;
+    assert!(unbounded >= 90_000 * 8, "{unbounded} bytes");
+    assert!(bounded > 0 && bounded <= 1000 * 32, "{bounded} bytes");
+
+    // The default bound applies to a cache far larger than it, whose weighted buckets
+    // are not allocated upfront
+    let cache = S3FIFO::<u64, u64>::new_weighted(1 << 30, |_, _| 1);
+    assert!(cache
+        .describe()
+        .contains(&format!("ghost_capacity: {DEFAULT_MAX_GHOST_SIZE},")));
+}
+
+#[test]
+fn reserve_makes_room_for_a_bulk_load() {
+    // Without a ghost FIFO, which would grow on its own as the small FIFO evicts
+    let config = Config {
+        ghost_ratio: 0.0,
+        ..Config::with_capacity(10_000)
+    };
+    let load = |cache: &mut S3FIFO<u64, u64>| {
+        for k in 0..5000 {
+            cache.put(k, k);
+            cache.get(&k);
+            cache.get(&k);
+        }
+        assert_eq!(cache.len(), 5000);
+    };
+
+    // An emptied cache gives up its memory and grows again as it loads
+    let mut cache = S3FIFO::from_config(config, Default::default()).unwrap();
+    cache.shrink_to_fit();
+    let empty = cache.memory_footprint();
+    load(&mut cache);
+    assert!(cache.memory_footprint() > empty);
+
+    cache.clear();
+    cache.shrink_to_fit();
+    assert_eq!(cache.memory_footprint(), empty);
+    cache.reserve(5000);
+    let reserved = cache.memory_footprint();
+    assert!(reserved > empty);
+    load(&mut cache);
+    assert_eq!(cache.memory_footprint(), reserved);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+#[test]
+fn memory_footprint_follows_the_allocations() {
+    // A weighted cache allocates its buckets as they come
+    let mut cache = S3FIFO::<u64, u64>::new_weighted(1000, |_, _| 1);
+    let empty = cache.memory_footprint();
+    let mut footprints = vec![empty];
+    for k in 0..1000 {
+        cache.put(k, k);
+        cache.get(&k);
+        cache.get(&k);
+        if k % 100 == 99 {
+            footprints.push(cache.memory_footprint());
+        }
+    }
+    assert!(footprints.windows(2).all(|w| w[0] <= w[1]));
+    let full = cache.memory_footprint();
+    assert!(full >= empty + 1000 * 2 * std::mem::size_of::<u64>());
+
+    // Clearing keeps the allocations for the next entries, shrinking releases them
+    cache.clear();
+    assert_eq!(cache.memory_footprint(), full);
+    cache.shrink_to_fit();
+    assert_eq!(cache.memory_footprint(), empty);
+}
+
+#[test]
+fn huge_capacities_never_overflow() {
+    // The allocation fails cleanly rather than on an overflow of the sizes
+    let message = |result: std::thread::Result<()>| {
+        let payload = result.unwrap_err();
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap()
+    };
+    for cap in [usize::MAX / 2 + 1, usize::MAX] {
+        let new = panic::catch_unwind(|| drop(S3FIFO::<u64, u64>::new(cap)));
+        assert!(message(new).contains("capacity overflow"), "capacity {cap}");
+        let reserve = panic::catch_unwind(|| S3FIFO::<u64, u64>::new(10).reserve(cap));
+        assert!(
+            message(reserve).contains("capacity overflow"),
+            "capacity {cap}"
+        );
+    }
+
+    // A weighted cache allocates as the entries come, whatever its capacity
+    let mut cache = S3FIFO::<u64, u64>::new_weighted(usize::MAX, |_, _| 1);
+    cache.resize(usize::MAX / 2 + 1);
+    for k in 0..100 {
+        cache.put(k, k);
+    }
+    assert_eq!(cache.get(&99), Some(&99));
+    cache.reset_to_capacity(usize::MAX);
+    cache.put(1, 1);
+    assert_eq!(cache.len(), 1);
+}
@@ -0,0 +1,98 @@
+//! Tests of the zero and tiny capacities
+
+use s3fifo::{CapacityError, S3FIFO};
+
+#[test]
+fn zero_capacity_reads_nothing_and_never_panics() {
+    let mut cache: S3FIFO<u32, u32> = S3FIFO::new(0);
+    assert_eq!(cache.put(1, 1), None);
+    assert_eq!(cache.put(1, 2), None);
+    assert_eq!((cache.len(), cache.weight(), cache.ghost_len()), (0, 0, 0));
+    assert!(cache.is_empty());
+    assert_eq!(
+        (
+            cache.capacity(),
+            cache.small_capacity(),
+            cache.main_capacity()
+        ),
+        (0, 0, 0)
+    );
+
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.get_mut(&1), None);
+    assert_eq!(cache.peek(&1), None);
+    assert_eq!(cache.peek_mut(&1), None);
+    assert!(!cache.contains_key(&1));
+    assert!(!cache.in_ghost(&1));
+    assert_eq!(cache.remove(&1), None);
+    assert_eq!(cache.pop_small(), None);
+    assert_eq!(cache.pop_main(), None);
+    assert_eq!(
+        cache.try_get_or_insert_with(1, || -> Result<u32, CapacityError> { Ok(1) }),
+        Err(CapacityError)
+    );
+
+    assert_eq!(cache.iter().count(), 0);
+    assert_eq!(cache.iter_mut().count(), 0);
+    assert_eq!(cache.keys().count(), 0);
+    assert_eq!(cache.values().count(), 0);
+    assert_eq!(cache.values_mut().count(), 0);
+    assert_eq!(cache.debug_entries().count(), 0);
+    assert_eq!(cache.fold_values(0, |sum, v| sum + v), 0);
+    cache.for_each_mut(|_, _| unreachable!());
+    assert!(cache.cursor_front_mut().current().is_none());
+    cache.retain(|_, _| unreachable!());
+    assert_eq!(cache.drain().count(), 0);
+
+    cache.clear();
+    cache.shrink_to_fit();
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+    assert!(!cache.describe().is_empty());
+    assert_eq!(cache.clone().into_iter().count(), 0);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn every_fifo_has_a_slot_for_any_capacity() {
+    for (cap, small, main) in [
+        (1, 1, 1),
+        (2, 1, 1),
+        (5, 1, 4),
+        (9, 1, 8),
+        (10, 1, 9),
+        (11, 1, 10),
+        (20, 2, 18),
+    ] {
+        let cache: S3FIFO<u32, u32> = S3FIFO::new(cap);
+        assert_eq!(
+            (cache.small_capacity(), cache.main_capacity()),
+            (small, main),
+            "capacity {cap}"
+        );
+    }
+}
+
+#[test]
+fn tiny_capacities_keep_caching() {
+    for cap in [1, 2, 5] {
+        let mut cache = S3FIFO::new(cap);
+        cache.put(1000, 1000);
+        for k in 0..50 {
+            cache.put(k, k);
+            assert_eq!(cache.get(&k), Some(&k), "capacity {cap}");
+            if k % 2 == 0 {
+                cache.get(&k);
+            }
+            // The hot key is put back whenever it was evicted, and soon stays
+            if cache.get(&1000).is_none() {
+                cache.put(1000, 1000);
+            }
+            assert!(cache.small_len() <= cache.small_capacity());
+            assert!(cache.main_len() <= cache.main_capacity());
+            #[cfg(debug_assertions)]
+            cache.assert_consistent();
+        }
+        assert_eq!(cache.get(&1000), Some(&1000), "capacity {cap}");
+    }
+}
@@ -0,0 +1,56 @@
+//! Tests of cloning the cache
+
+use s3fifo::{Fifo, S3FIFO};
+
+fn entries(cache: &S3FIFO<u32, u32>) -> Vec<(u32, u32, u8, Fifo)> {
+    cache
+        .debug_entries()
+        .map(|(&k, freq, fifo)| (k, *cache.peek(&k).unwrap(), freq, fifo))
+        .collect()
+}
+
+#[test]
+fn clone_is_identical_and_independent() {
+    let mut cache = S3FIFO::new(100);
+    for k in 0..300 {
+        cache.put(k, k);
+        if k % 3 == 0 {
+            cache.get(&k);
+            cache.get(&k);
+        }
+    }
+    assert!(cache.small_len() > 0 && cache.main_len() > 0 && cache.ghost_len() > 0);
+
+    let mut clone = cache.clone();
+    #[cfg(debug_assertions)]
+    clone.assert_consistent();
+    assert_eq!(entries(&clone), entries(&cache));
+    assert_eq!(clone.ghost_len(), cache.ghost_len());
+    assert!((0..300).all(|k| clone.in_ghost(&k) == cache.in_ghost(&k)));
+
+    // The same workload leads both to the same state, the ghost FIFO was copied too
+    for k in 200..400 {
+        for c in [&mut cache, &mut clone] {
+            c.put(k, k + 1);
+            c.get(&(k / 2));
+        }
+    }
+    assert_eq!(entries(&clone), entries(&cache));
+
+    // Mutating one leaves the other untouched
+    let before = entries(&cache);
+    *clone.get_mut(&399).unwrap() = 0;
+    clone.remove(&398);
+    clone.put(1000, 1000);
+    clone.clear();
+    assert_eq!(entries(&cache), before);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+    #[cfg(debug_assertions)]
+    clone.assert_consistent();
+
+    let clone = cache.clone();
+    cache.put(1000, 1000);
+    *cache.get_mut(&399).unwrap() = 0;
+    assert_eq!(entries(&clone), before);
+}
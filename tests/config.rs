@@ -0,0 +1,83 @@
+//! Tests of the settings of `Config`
+
+use s3fifo::{Config, Fifo, S3FIFO};
+
+fn fifo_of(cache: &S3FIFO<u32, u32>, key: u32) -> Option<Fifo> {
+    cache
+        .debug_entries()
+        .find(|&(&k, _, _)| k == key)
+        .map(|(_, _, fifo)| fifo)
+}
+
+/// Put the keys 0 to 9 into the small FIFO of a cache with capacity 100, get the key
+/// `k` `k % 4` times, then push them all out of the small FIFO
+fn accessed_then_evicted(config: Config) -> S3FIFO<u32, u32> {
+    let mut cache = S3FIFO::from_config(config, Default::default()).unwrap();
+    assert_eq!(cache.small_capacity(), 10);
+    for k in 0..10 {
+        cache.put(k, k);
+        for _ in 0..k % 4 {
+            cache.get(&k);
+        }
+    }
+    for k in 100..110 {
+        cache.put(k, k);
+    }
+    cache
+}
+
+#[test]
+fn promotion_threshold_keeps_the_rarely_accessed_keys_out_of_main() {
+    let cache = accessed_then_evicted(Config::with_capacity(100));
+    for k in 0..10 {
+        // The frequency is decremented before it is compared to the threshold
+        let expected = (k % 4 >= 2).then_some(Fifo::Main);
+        assert_eq!(fifo_of(&cache, k), expected, "key {k}");
+        assert_eq!(cache.in_ghost(&k), k % 4 < 2, "key {k}");
+    }
+
+    let cache = accessed_then_evicted(Config {
+        promotion_threshold: 2,
+        ..Config::with_capacity(100)
+    });
+    for k in 0..10 {
+        let expected = (k % 4 >= 3).then_some(Fifo::Main);
+        assert_eq!(fifo_of(&cache, k), expected, "key {k}");
+        assert_eq!(cache.in_ghost(&k), k % 4 < 3, "key {k}");
+    }
+}
+
+#[test]
+fn new_for_hot_entries_sizes_the_main_fifo() {
+    for n in (0..2000).chain([9_999, 10_000, 123_456]) {
+        let cache = S3FIFO::<u32, u32>::new_for_hot_entries(n);
+        assert!(cache.main_capacity() >= n, "{n} hot entries");
+        // No more than one slot beyond n / 0.9
+        assert!(cache.capacity() <= n * 10 / 9 + 1, "{n} hot entries");
+    }
+}
+
+#[test]
+fn bump_small_on_get_decides_the_promotions() {
+    let config = Config {
+        bump_small_on_get: false,
+        ..Config::with_capacity(100)
+    };
+    let mut cache = accessed_then_evicted(config);
+    for k in 0..10 {
+        assert_eq!(fifo_of(&cache, k), None, "key {k}");
+        assert!(cache.in_ghost(&k), "key {k}");
+    }
+
+    // The entries of the main FIFO still count their accesses
+    cache.put(3, 3);
+    assert_eq!(fifo_of(&cache, 3), Some(Fifo::Main));
+    cache.get(&3);
+    cache.get(&3);
+    let freq = cache.debug_entries().find(|&(&k, _, _)| k == 3).unwrap().1;
+    assert_eq!(freq, 2);
+
+    // The same accesses promote with the flag set, as checked by the default config
+    let cache = accessed_then_evicted(Config::with_capacity(100));
+    assert_eq!(fifo_of(&cache, 3), Some(Fifo::Main));
+}
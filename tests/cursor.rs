@@ -0,0 +1,48 @@
+//! Tests of the cursor over the entries
+
+use s3fifo::S3FIFO;
+
+#[test]
+fn cursor_removes_every_third_entry() {
+    let mut cache = S3FIFO::new(100);
+    for k in 0..10 {
+        cache.put(k, k);
+        cache.get(&k);
+        cache.get(&k);
+    }
+    // The first put of the loop finds the small FIFO full and promotes 0 to 9
+    for k in 10..19 {
+        cache.put(k, k);
+    }
+    assert_eq!((cache.small_len(), cache.main_len()), (9, 10));
+
+    let mut visited = Vec::new();
+    let mut removed = Vec::new();
+    let mut cursor = cache.cursor_front_mut();
+    while let Some((&k, v)) = cursor.current() {
+        visited.push(k);
+        if visited.len() % 3 == 0 {
+            assert_eq!(cursor.remove_current(), Some((k, k)));
+            removed.push(k);
+        } else {
+            *v += 100;
+            cursor.move_next();
+        }
+    }
+
+    // From the front of the small FIFO to the back of the main FIFO
+    assert_eq!(visited, (10..19).chain(0..10).collect::<Vec<_>>());
+    assert_eq!(removed, [12, 15, 18, 2, 5, 8]);
+    assert_eq!(cache.len(), 13);
+    assert_eq!((cache.small_len(), cache.main_len()), (6, 7));
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+    for k in 0..19 {
+        if removed.contains(&k) {
+            assert_eq!(cache.get(&k), None);
+            assert!(!cache.in_ghost(&k));
+        } else {
+            assert_eq!(cache.get(&k), Some(&(k + 100)));
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Tests of the one line descriptions of the cache
+
+use s3fifo::S3FIFO;
+
+/// Features of the crate that `describe` lists
+fn compiled_features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "ttl") {
+        features.push("ttl");
+    }
+    if cfg!(feature = "stats") {
+        features.push("stats");
+    }
+    features
+}
+
+#[test]
+fn describe_extends_the_display_summary() {
+    let mut cache = S3FIFO::new(100);
+    // The keys accessed twice are all promoted once the small FIFO is full
+    for k in 0..12u32 {
+        cache.put(k, k);
+        cache.get(&k);
+        cache.get(&k);
+    }
+    assert_eq!(
+        cache.to_string(),
+        "S3FIFO(len=12/100, small=2/10, main=10/90, ghost=0)"
+    );
+    assert_eq!(
+        cache.describe(),
+        format!(
+            "S3FIFO(len=12/100, small=2/10, main=10/90, ghost=0) {{ small_len: 2, main_len: 10, ghost_capacity: 90, max_freq: 3, features: [{}] }}",
+            compiled_features().join(", ")
+        )
+    );
+
+    let mut weighted = S3FIFO::new_weighted(100, |_: &u32, v: &Vec<u8>| v.len());
+    weighted.put(1, vec![0; 4]);
+    weighted.put(2, vec![0; 3]);
+    let mut features = vec!["weighted"];
+    features.extend(compiled_features());
+    assert_eq!(
+        weighted.describe(),
+        format!(
+            "S3FIFO(len=7/100, small=7/10, main=0/90, ghost=0) {{ small_len: 2, main_len: 0, ghost_capacity: 90, max_freq: 3, features: [{}] }}",
+            features.join(", ")
+        )
+    );
+}
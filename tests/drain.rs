@@ -0,0 +1,87 @@
+//! Tests of `drain` and of consuming the cache
+
+use s3fifo::S3FIFO;
+
+/// Put the keys 0 to 9 in the main FIFO and 10 to 18 in the small FIFO of an empty
+/// cache of capacity 100
+fn fill(cache: &mut S3FIFO<u32, u32>) {
+    for k in 0..10 {
+        cache.put(k, k);
+        cache.get(&k);
+        cache.get(&k);
+    }
+    // The first put of the loop finds the small FIFO full and promotes 0 to 9
+    for k in 10..19 {
+        cache.put(k, k);
+    }
+    assert_eq!((cache.small_len(), cache.main_len()), (9, 10));
+}
+
+fn filled() -> S3FIFO<u32, u32> {
+    let mut cache = S3FIFO::new(100);
+    fill(&mut cache);
+    cache
+}
+
+#[test]
+fn drain_leaves_a_reusable_cache_of_the_same_capacities() {
+    let mut cache = filled();
+    let capacities = (
+        cache.capacity(),
+        cache.small_capacity(),
+        cache.main_capacity(),
+    );
+    let mut drained = cache.drain().collect::<Vec<_>>();
+    drained.sort_unstable();
+    assert_eq!(drained, (0..19).map(|k| (k, k)).collect::<Vec<_>>());
+    assert!(cache.is_empty());
+    assert_eq!((cache.small_len(), cache.main_len()), (0, 0));
+    assert_eq!(
+        (
+            cache.capacity(),
+            cache.small_capacity(),
+            cache.main_capacity()
+        ),
+        capacities
+    );
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+
+    // The next phase fills the cache again without growing it
+    let footprint = cache.memory_footprint();
+    fill(&mut cache);
+    for k in 0..19 {
+        assert_eq!(cache.get(&k), Some(&k));
+    }
+    assert_eq!(cache.memory_footprint(), footprint);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+#[test]
+fn into_iter_yields_every_entry() {
+    let mut entries = filled().into_iter().collect::<Vec<_>>();
+    entries.sort_unstable();
+    assert_eq!(entries, (0..19).map(|k| (k, k)).collect::<Vec<_>>());
+}
+
+#[test]
+fn dropping_a_half_consumed_drain_empties_the_cache() {
+    let mut cache = filled();
+    let mut drained = cache.drain().take(9).collect::<Vec<_>>();
+    drained.sort_unstable();
+    drained.dedup();
+    assert_eq!(drained.len(), 9);
+    assert!(drained.iter().all(|&(k, v)| k == v && k < 19));
+
+    assert_eq!(cache.len(), 0);
+    assert_eq!((cache.small_len(), cache.main_len()), (0, 0));
+    for k in 0..19 {
+        assert_eq!(cache.get(&k), None);
+    }
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+    fill(&mut cache);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
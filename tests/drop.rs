@@ -0,0 +1,56 @@
+//! Tests that every value is dropped exactly once
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use s3fifo::S3FIFO;
+
+/// Value that counts its drops in a shared counter
+struct Counted(Rc<Cell<usize>>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+#[test]
+fn every_value_is_dropped_exactly_once() {
+    let drops = Rc::new(Cell::new(0));
+    let mut created = 0;
+    let mut counted = || {
+        created += 1;
+        Counted(drops.clone())
+    };
+    let live = |cache: &S3FIFO<u32, Counted>| {
+        assert_eq!(Rc::strong_count(&drops) - 1, cache.len());
+        #[cfg(debug_assertions)]
+        cache.assert_consistent();
+    };
+
+    let mut cache = S3FIFO::new(100);
+    // Evictions from both FIFOs and replacements of cached values
+    for k in 0..1000 {
+        cache.put(k % 300, counted());
+        if k % 4 == 0 {
+            cache.get(&(k % 300));
+            cache.get(&(k % 300));
+        }
+        live(&cache);
+    }
+    for k in 0..300 {
+        drop(cache.remove(&k));
+        live(&cache);
+        cache.put(k, counted());
+    }
+    cache.clear();
+    live(&cache);
+    for k in 0..50 {
+        cache.put(k, counted());
+    }
+    live(&cache);
+    assert_eq!(drops.get() + cache.len(), created);
+    drop(cache);
+    assert_eq!(drops.get(), created);
+    assert_eq!(Rc::strong_count(&drops), 1);
+}
@@ -0,0 +1,45 @@
+//! Tests of the eviction listener
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use s3fifo::S3FIFO;
+
+#[test]
+fn listener_fires_once_per_real_eviction() {
+    let evicted = Arc::new(Mutex::new(Vec::new()));
+    let sink = evicted.clone();
+    let mut cache =
+        S3FIFO::new(100).with_eviction_listener(move |k, v| sink.lock().unwrap().push((k, v)));
+
+    let mut inserted = 0;
+    let mut last = HashMap::new();
+    for k in 0..5000u32 {
+        let key = k % 400;
+        if !cache.contains_key(&key) {
+            inserted += 1;
+        }
+        cache.put(key, k);
+        last.insert(key, k);
+        if key % 3 == 0 {
+            cache.get(&key);
+            cache.get(&key);
+        }
+
+        // The entries are handed over with their last value, and none of them is
+        // still cached: promotions and frequency decays do not fire the listener
+        for (key, v) in evicted.lock().unwrap().drain(..) {
+            assert!(!cache.contains_key(&key), "{key} is still cached");
+            assert_eq!(last[&key], v);
+            inserted -= 1;
+        }
+    }
+    // The accessed keys were promoted along the way
+    assert!(cache.main_len() > 0);
+    assert_eq!(inserted, cache.len());
+
+    // Explicit removals and clearing are not evictions
+    cache.remove(&0);
+    cache.clear();
+    assert!(evicted.lock().unwrap().is_empty());
+}
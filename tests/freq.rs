@@ -0,0 +1,32 @@
+//! Tests of the frequency counters of the entries
+
+use s3fifo::{Config, S3FIFO};
+
+fn freq(cache: &S3FIFO<u32, u32>, key: u32) -> u8 {
+    cache
+        .debug_entries()
+        .find(|&(&k, _, _)| k == key)
+        .map(|(_, freq, _)| freq)
+        .unwrap()
+}
+
+#[test]
+fn frequency_saturates_at_max_freq() {
+    let mut cache = S3FIFO::new(100);
+    cache.put(1, 1);
+    for accesses in 1..=12u8 {
+        cache.get(&1);
+        assert_eq!(freq(&cache, 1), accesses.min(3), "{accesses} accesses");
+    }
+
+    let config = Config {
+        max_freq: 7,
+        ..Config::with_capacity(100)
+    };
+    let mut cache = S3FIFO::from_config(config, Default::default()).unwrap();
+    cache.put(1, 1);
+    for _ in 0..300 {
+        cache.get(&1);
+    }
+    assert_eq!(freq(&cache, 1), 7);
+}
@@ -0,0 +1,73 @@
+//! Tests of building a cache from an iterator
+
+use s3fifo::S3FIFO;
+
+#[test]
+fn from_iter_with_capacity_keeps_a_fitting_iterator() {
+    let mut cache = S3FIFO::from_iter_with_capacity(1000, (0..1000).map(|k| (k, k)));
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+    assert_eq!(cache.len(), 1000);
+    assert_eq!((cache.small_len(), cache.main_len()), (100, 900));
+    for k in 0..1000 {
+        assert_eq!(cache.get(&k), Some(&k));
+    }
+
+    // Fewer keys than the capacity, with a duplicate that overwrites
+    let cache = S3FIFO::from_iter_with_capacity(1000, (0..500).map(|k| (k % 400, k)));
+    assert_eq!(cache.len(), 400);
+    assert_eq!(cache.peek(&10), Some(&410));
+    assert_eq!(cache.peek(&399), Some(&399));
+}
+
+#[test]
+fn from_iter_with_capacity_evicts_the_first_keys_beyond_the_capacity() {
+    let cache = S3FIFO::from_iter_with_capacity(1000, (0..1050).map(|k| (k, k)));
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+    assert_eq!(cache.len(), 1000);
+    // The keys beyond the capacity evicted the oldest keys of the small FIFO
+    for k in 0..50 {
+        assert!(!cache.contains_key(&k));
+    }
+    for k in 50..1050 {
+        assert!(cache.contains_key(&k));
+    }
+}
+
+#[test]
+fn collect_keeps_every_pair() {
+    let cache: S3FIFO<u32, u32> = (0..1000).map(|k| (k, k)).collect();
+    assert_eq!(cache.capacity(), 1000);
+    assert_eq!(cache.len(), 1000);
+
+    // The upper bound of the size hint overestimates a filtered iterator
+    let cache: S3FIFO<u32, u32> = (0..1000).filter(|k| k % 2 == 0).map(|k| (k, k)).collect();
+    assert_eq!(cache.capacity(), 1000);
+    assert_eq!(cache.len(), 500);
+
+    // Without an upper bound the pairs are counted
+    let mut keys = 0..300;
+    let cache: S3FIFO<u32, u32> = std::iter::from_fn(|| keys.next().map(|k| (k, k))).collect();
+    assert_eq!(cache.capacity(), 300);
+    assert_eq!(cache.len(), 300);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+#[test]
+fn collect_and_extend_keep_the_last_value_of_a_key() {
+    let pairs: Vec<(u32, u32)> = (0..1000).map(|i| (i % 100, i)).collect();
+    let cache: S3FIFO<u32, u32> = pairs.iter().copied().collect();
+    assert_eq!(cache.len(), 100);
+    for k in 0..100 {
+        assert_eq!(cache.peek(&k), Some(&(900 + k)));
+    }
+
+    let mut cache = S3FIFO::new(1000);
+    cache.extend(pairs);
+    assert_eq!(cache.len(), 100);
+    for k in 0..100 {
+        assert_eq!(cache.peek(&k), Some(&(900 + k)));
+    }
+}
@@ -0,0 +1,143 @@
+//! Tests of the lookups that insert on a miss
+
+use std::panic::{self, AssertUnwindSafe};
+
+use s3fifo::{CapacityError, S3FIFO};
+
+fn freq(cache: &S3FIFO<u32, u32>, key: u32) -> u8 {
+    cache
+        .debug_entries()
+        .find(|&(&k, _, _)| k == key)
+        .map(|(_, freq, _)| freq)
+        .unwrap()
+}
+
+#[test]
+fn get_or_insert_with_only_calls_the_closure_on_a_miss() {
+    let mut cache = S3FIFO::new(10);
+    let mut calls = 0;
+    assert_eq!(
+        *cache.get_or_insert_with(1, || {
+            calls += 1;
+            10
+        }),
+        10
+    );
+    assert_eq!(calls, 1);
+    assert_eq!(freq(&cache, 1), 0);
+
+    // A hit returns the cached value, bumps its frequency and never calls the closure
+    let v = cache.get_or_insert_with(1, || {
+        calls += 1;
+        20
+    });
+    assert_eq!(*v, 10);
+    *v = 11;
+    assert_eq!(calls, 1);
+    assert_eq!(freq(&cache, 1), 1);
+    assert_eq!(cache.peek(&1), Some(&11));
+    assert_eq!(cache.len(), 1);
+}
+
+/// Error of a fallible construction of a value
+#[derive(Debug, PartialEq)]
+enum Error {
+    Capacity,
+    Failed,
+}
+
+impl From<CapacityError> for Error {
+    fn from(_: CapacityError) -> Self {
+        Self::Capacity
+    }
+}
+
+#[test]
+fn failing_closure_leaves_the_cache_untouched() {
+    let mut cache = S3FIFO::new(100);
+    for k in 0..10 {
+        cache.put(k, k);
+    }
+    // The small FIFO is full, an insertion would evict 0 into the ghost FIFO
+    assert_eq!(cache.ghost_len(), 0);
+    let before: Vec<_> = cache
+        .debug_entries()
+        .map(|(&k, f, fifo)| (k, f, fifo))
+        .collect();
+
+    let err = cache.try_get_or_insert_with(100, || Err::<u32, _>(Error::Failed));
+    assert_eq!(err, Err(Error::Failed));
+    let after: Vec<_> = cache
+        .debug_entries()
+        .map(|(&k, f, fifo)| (k, f, fifo))
+        .collect();
+    assert_eq!(after, before);
+    assert_eq!(cache.ghost_len(), 0);
+    assert!(!cache.in_ghost(&0) && !cache.in_ghost(&100));
+    assert!(!cache.contains_key(&100));
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+
+    // The same insertion succeeds afterwards and only then evicts
+    assert_eq!(
+        cache.try_get_or_insert_with(100, || Ok::<_, Error>(100)),
+        Ok(&mut 100)
+    );
+    assert!(cache.in_ghost(&0));
+    assert_eq!(cache.ghost_len(), 1);
+
+    // Without room for the key, the closure is not even called
+    let mut cache = S3FIFO::<u32, u32>::new(0);
+    let mut called = false;
+    let err = cache.try_get_or_insert_with(1, || {
+        called = true;
+        Ok::<_, Error>(1)
+    });
+    assert_eq!(err, Err(Error::Capacity));
+    assert!(!called);
+}
+
+#[test]
+fn get_or_insert_with_key_only_builds_the_key_on_a_miss() {
+    let mut cache = S3FIFO::<String, u32>::new(10);
+    let (mut keys, mut values) = (0, 0);
+    for _ in 0..3 {
+        let v = cache.get_or_insert_with_key(
+            "one",
+            || {
+                keys += 1;
+                String::from("one")
+            },
+            || {
+                values += 1;
+                1
+            },
+        );
+        *v += 1;
+    }
+    assert_eq!((keys, values), (1, 1));
+    assert_eq!(cache.peek("one"), Some(&4));
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn get_or_insert_with_key_builds_nothing_without_room() {
+    let mut cache = S3FIFO::<String, u32>::new(0);
+    let (mut keys, mut values) = (0, 0);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        cache.get_or_insert_with_key(
+            "one",
+            || {
+                keys += 1;
+                String::from("one")
+            },
+            || {
+                values += 1;
+                1
+            },
+        );
+    }));
+    assert!(result.is_err());
+    assert_eq!((keys, values), (0, 0));
+    assert!(cache.is_empty());
+}
@@ -0,0 +1,31 @@
+//! Tests of the ghost FIFO, which remembers the hashes of the evicted keys
+
+use s3fifo::S3FIFO;
+
+#[test]
+fn ghost_forgets_the_oldest_hashes_first() {
+    // The small FIFO holds 10 keys and the ghost FIFO 90 hashes
+    let mut cache = S3FIFO::new(100);
+    for k in 0..500u32 {
+        cache.put(k, k);
+        // Without accesses, the keys are evicted from the small FIFO in order, and
+        // the ghost FIFO remembers the last 90 of them
+        let evicted = (k + 1).saturating_sub(10);
+        let remembered = evicted.saturating_sub(90)..evicted;
+        assert_eq!(cache.ghost_len(), remembered.len());
+        for j in 0..=k {
+            assert_eq!(cache.in_ghost(&j), remembered.contains(&j), "{j} after {k}");
+        }
+    }
+    assert!(!cache.in_ghost(&500));
+
+    // A remembered key is admitted into the main FIFO, while a forgotten one enters
+    // the small FIFO again
+    cache.put(450, 450);
+    cache.put(300, 300);
+    assert_eq!((cache.small_len(), cache.main_len()), (10, 1));
+    assert_eq!(cache.get(&450), Some(&450));
+    assert!(!cache.contains_key(&490));
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
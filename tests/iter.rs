@@ -0,0 +1,26 @@
+//! Tests of the iterators over the entries
+
+use s3fifo::S3FIFO;
+
+#[test]
+fn iter_mut_visits_each_entry_once() {
+    let mut cache = S3FIFO::new(10);
+    for i in 0..20 {
+        cache.put(i, i);
+        cache.get(&i);
+    }
+    cache.remove(&15);
+
+    assert_eq!(cache.iter_mut().count(), cache.len());
+    for (&k, v) in cache.iter_mut() {
+        assert_eq!(k, *v);
+        *v += 100;
+    }
+    let mut seen: Vec<_> = cache.iter().map(|(&k, &v)| (k, v)).collect();
+    seen.sort_unstable();
+    let mut expected: Vec<_> = cache.keys().map(|&k| (k, k + 100)).collect();
+    expected.sort_unstable();
+    assert_eq!(seen, expected);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
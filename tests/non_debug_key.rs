@@ -0,0 +1,31 @@
+//! Tests of a key type that does not implement `Debug`
+
+use std::collections::hash_map::RandomState;
+
+use s3fifo::S3FIFO;
+
+/// Key without `Debug`, which the cache must not require
+#[derive(PartialEq, Eq, Hash, Clone)]
+struct Key(u32);
+
+#[test]
+fn non_debug_keys_are_cached_and_evicted() {
+    let mut cache = S3FIFO::with_hasher(10, RandomState::new());
+    for k in 0..100 {
+        cache.put(Key(k), k);
+        if k % 2 == 0 {
+            cache.get(&Key(k));
+            cache.get(&Key(k));
+        }
+    }
+    assert_eq!(cache.len(), 10);
+    assert_eq!(cache.get(&Key(99)), Some(&99));
+    assert_eq!(*cache.get_or_insert_with(Key(1000), || 1000), 1000);
+    assert_eq!(cache.remove(&Key(1000)), Some(1000));
+
+    let mut cache = S3FIFO::new(1);
+    cache.put(Key(1), 1);
+    cache.put(Key(2), 2);
+    assert_eq!(cache.get(&Key(2)), Some(&2));
+    assert_eq!(cache.len(), 1);
+}
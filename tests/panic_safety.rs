@@ -0,0 +1,117 @@
+//! Tests that a panic in the user code, or a leaked iterator, leaves the cache
+//! consistent
+
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+
+use s3fifo::S3FIFO;
+
+/// Cache with the keys 6 to 14 in the small FIFO and 0 to 5 in the main FIFO
+fn filled_cache() -> S3FIFO<u32, u32> {
+    let mut cache = S3FIFO::new(100);
+    for k in 0..10 {
+        cache.put(k, k);
+    }
+    for k in 0..5 {
+        cache.get(&k);
+        cache.get(&k);
+    }
+    // The first put promotes 0 to 4 and evicts 5 to the ghost FIFO
+    for k in 10..15 {
+        cache.put(k, k);
+    }
+    // Admitted into the main FIFO by the ghost FIFO
+    cache.put(5, 5);
+    assert_eq!((cache.small_len(), cache.main_len()), (9, 6));
+    cache
+}
+
+#[test]
+fn panicking_retain_removes_nothing() {
+    let mut cache = filled_cache();
+    let len = cache.len();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        cache.retain(|&k, _| {
+            assert!(k != 12, "retain panics");
+            k % 2 == 0
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(cache.len(), len);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+
+    cache.retain(|&k, _| k % 2 == 0);
+    assert!(cache.keys().all(|k| k % 2 == 0));
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+#[test]
+fn panicking_invalidate_entries_if_removes_nothing() {
+    let mut cache = filled_cache();
+    let len = cache.len();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        cache.invalidate_entries_if(|&k, _| {
+            assert!(k != 3, "predicate panics");
+            true
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(cache.len(), len);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+#[test]
+fn leaked_drain_leaves_the_cache_empty() {
+    let mut cache = filled_cache();
+    let mut drain = cache.drain();
+    drain.next();
+    mem::forget(drain);
+    assert!(cache.is_empty());
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+
+    cache.put(1, 1);
+    assert_eq!(cache.get(&1), Some(&1));
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+#[test]
+fn drain_keeps_the_allocations() {
+    let mut cache = filled_cache();
+    let footprint = cache.memory_footprint();
+    assert_eq!(cache.drain().take(3).count(), 3);
+    assert!(cache.is_empty());
+    assert_eq!(cache.memory_footprint(), footprint);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+/// Value whose drop panics once the flag is set
+struct PanicOnDrop(bool);
+
+impl Drop for PanicOnDrop {
+    fn drop(&mut self) {
+        if self.0 && !std::thread::panicking() {
+            panic!("drop panics");
+        }
+    }
+}
+
+#[test]
+fn panicking_drop_in_drain_leaves_the_cache_empty() {
+    let mut cache = S3FIFO::new(100);
+    for k in 0..5 {
+        cache.put(k, PanicOnDrop(k == 3));
+    }
+    let result = panic::catch_unwind(AssertUnwindSafe(|| drop(cache.drain())));
+    assert!(result.is_err());
+    assert!(cache.is_empty());
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+    cache.put(7, PanicOnDrop(false));
+    assert!(cache.contains_key(&7));
+}
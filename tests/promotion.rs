@@ -0,0 +1,55 @@
+//! Tests of the promotions from the small to the main FIFO
+
+use std::collections::HashMap;
+
+use s3fifo::{Fifo, S3FIFO};
+
+/// Promote keys while reading the ones promoted before, and check every value read
+fn stress(mut cache: S3FIFO<u32, u64>) {
+    let mut last = HashMap::new();
+    let mut promoted = Vec::new();
+    let mut state = 1u64;
+    for step in 0..20_000u64 {
+        // Linear congruential generator, skewed towards the small keys
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1);
+        let key = ((state >> 33) % 1000) as u32 % ((state >> 50) as u32 % 1000 + 1);
+        let value = step << 32 | key as u64;
+        cache.put(key, value);
+        last.insert(key, value);
+        if step % 2 == 0 {
+            assert_eq!(cache.get(&key), Some(&value));
+            cache.get(&key);
+        }
+        for &k in promoted.iter().rev().take(8) {
+            if let Some(v) = cache.get(&k) {
+                assert_eq!(*v, last[&k], "key {k} at step {step}");
+            }
+        }
+        if let Some((_, _, Fifo::Main)) = cache.debug_entries().find(|&(&k, _, _)| k == key) {
+            promoted.push(key);
+        }
+        if step % 1000 == 0 {
+            #[cfg(debug_assertions)]
+            cache.assert_consistent();
+        }
+    }
+    assert!(promoted.len() > 1000);
+    for (&k, &v) in cache.iter() {
+        assert_eq!(v, last[&k]);
+    }
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+#[test]
+fn promotions_keep_the_values_of_the_promoted_keys() {
+    stress(S3FIFO::new(200));
+}
+
+#[test]
+fn promotions_keep_the_values_while_the_arena_grows() {
+    // A weighted cache allocates its buckets as they come
+    stress(S3FIFO::new_weighted(200, |_, _| 1));
+}
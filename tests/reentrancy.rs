@@ -0,0 +1,31 @@
+//! Tests of the callbacks that try to reach the cache that calls them
+
+#![cfg(feature = "std")]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, TryLockError};
+
+use s3fifo::S3FIFO;
+
+/// Slot through which the callback reaches the cache that calls it
+type Shared = Arc<OnceLock<Arc<Mutex<S3FIFO<u32, u32>>>>>;
+
+#[test]
+fn access_callback_can_not_lock_the_cache_that_calls_it() {
+    let shared: Shared = Arc::new(OnceLock::new());
+    let reached = Arc::new(AtomicBool::new(false));
+    let (slot, callback_reached) = (Arc::clone(&shared), Arc::clone(&reached));
+    let cache = S3FIFO::new(10).with_on_access(move |_| {
+        let cache = slot.get().unwrap();
+        assert!(matches!(cache.try_lock(), Err(TryLockError::WouldBlock)));
+        callback_reached.store(true, Ordering::Relaxed);
+    });
+    let cache = shared.get_or_init(|| Arc::new(Mutex::new(cache)));
+
+    let mut guard = cache.lock().unwrap();
+    guard.put(1, 1);
+    assert_eq!(guard.get(&1), Some(&1));
+    assert!(reached.load(Ordering::Relaxed));
+    #[cfg(debug_assertions)]
+    guard.assert_consistent();
+}
@@ -0,0 +1,140 @@
+//! Tests of the removal of entries from anywhere in the FIFOs
+
+use s3fifo::{Entry, Fifo, S3FIFO};
+
+/// Cache with the keys 0 to 7 in the small FIFO and 100 to 107 in the main FIFO
+fn two_fifos() -> S3FIFO<u32, u32> {
+    let mut cache = S3FIFO::new(100);
+    // Evicted from a full small FIFO to the ghost FIFO, then admitted to the main FIFO
+    for k in (100..108).chain(0..10) {
+        cache.put(k, k);
+    }
+    for k in 100..108 {
+        cache.put(k, k);
+    }
+    cache.remove(&8);
+    cache.remove(&9);
+    assert_eq!((cache.small_len(), cache.main_len()), (8, 8));
+    cache
+}
+
+fn keys(cache: &S3FIFO<u32, u32>) -> Vec<(u32, Fifo)> {
+    cache
+        .debug_entries()
+        .map(|(&k, _, fifo)| (k, fifo))
+        .collect()
+}
+
+#[test]
+fn removing_from_the_middle_keeps_the_order() {
+    let mut cache = two_fifos();
+    let mut expected = keys(&cache);
+    for k in [3, 0, 7, 104, 100, 107] {
+        assert_eq!(cache.remove(&k), Some(k));
+        expected.retain(|&(probe, _)| probe != k);
+        assert_eq!(keys(&cache), expected);
+        #[cfg(debug_assertions)]
+        cache.assert_consistent();
+    }
+    assert_eq!(cache.remove(&3), None);
+
+    // The removed buckets are reused and the new keys go to the back
+    cache.put(50, 50);
+    expected.insert(5, (50, Fifo::Small));
+    assert_eq!(keys(&cache), expected);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+#[test]
+fn every_removal_path_unlinks_the_bucket() {
+    let mut cache = two_fifos();
+    assert_eq!(cache.take_if(&2, |&v| v == 2), Some(2));
+    assert_eq!(cache.take_if(&5, |&v| v == 0), None);
+    match cache.entry(105) {
+        Entry::Occupied(entry) => assert_eq!(entry.remove(), 105),
+        Entry::Vacant(_) => unreachable!(),
+    }
+    assert_eq!(cache.pop_small(), Some((0, 0)));
+    assert_eq!(cache.pop_main(), Some((100, 100)));
+
+    let mut cursor = cache.cursor_front_mut();
+    while let Some((&k, _)) = cursor.current() {
+        if k % 2 == 1 {
+            assert_eq!(cursor.remove_current(), Some((k, k)));
+        } else {
+            cursor.move_next();
+        }
+    }
+    assert_eq!(
+        keys(&cache),
+        [
+            (4, Fifo::Small),
+            (6, Fifo::Small),
+            (102, Fifo::Main),
+            (104, Fifo::Main),
+            (106, Fifo::Main)
+        ]
+    );
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+
+    // The FIFOs still evict in order after the removals
+    for k in 200..210 {
+        cache.put(k, k);
+    }
+    assert!(!cache.contains_key(&4));
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+/// Mix the insertions, evictions and every removal path on a small cache, sized to run
+/// under Miri: `cargo +nightly miri test --test removal`
+#[test]
+fn removal_under_churn() {
+    let mut cache = S3FIFO::new(16);
+    let mut state = 1u32;
+    for step in 0..600u32 {
+        // Xorshift, deterministic so that a failure reproduces
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let k = state % 40;
+        match step % 8 {
+            0..=2 => {
+                cache.put(k, step);
+            }
+            3 => {
+                cache.get(&k);
+            }
+            4 => {
+                cache.remove(&k);
+            }
+            5 => {
+                cache.take_if(&k, |&v| v % 2 == 0);
+            }
+            6 => {
+                if let Entry::Occupied(entry) = cache.entry(k) {
+                    entry.remove();
+                }
+            }
+            _ => {
+                let mut cursor = cache.cursor_front_mut();
+                for _ in 0..k % 5 {
+                    cursor.move_next();
+                }
+                cursor.remove_current();
+            }
+        }
+        if step % 100 == 99 {
+            cache.retain(|&k, _| k % 3 != 0);
+            cache.shrink_to_fit();
+        }
+        #[cfg(debug_assertions)]
+        cache.assert_consistent();
+    }
+    let len = cache.len();
+    assert_eq!(cache.drain().count(), len);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
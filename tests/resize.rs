@@ -0,0 +1,90 @@
+//! Tests of the changes of the capacities at runtime
+
+use s3fifo::{Fifo, S3FIFO};
+
+/// Cache with capacity 1000 through which 3000 keys went, a third of them accessed
+fn populated() -> S3FIFO<u32, u32> {
+    let mut cache = S3FIFO::new(1000);
+    for k in 0..3000 {
+        cache.put(k, k * 2);
+        if k % 3 == 0 {
+            cache.get(&k);
+            cache.get(&k);
+        }
+    }
+    cache
+}
+
+/// Observable outcomes of a workload
+#[derive(Debug, PartialEq)]
+struct Trace {
+    hits: Vec<Option<u32>>,
+    /// Lengths of the small, main and ghost FIFOs after each step
+    lens: Vec<(usize, usize, usize)>,
+    entries: Vec<(u32, u8, Fifo)>,
+}
+
+/// Run a workload that promotes, evicts and readmits through the ghost FIFO
+fn trace(cache: &mut S3FIFO<u32, u32>) -> Trace {
+    let mut hits = Vec::new();
+    let mut lens = Vec::new();
+    for k in 0..500 {
+        cache.put(k % 70, k);
+        hits.push(cache.get(&(k % 7)).copied());
+        hits.push(cache.get(&(k % 70)).copied());
+        lens.push((cache.small_len(), cache.main_len(), cache.ghost_len()));
+    }
+    let entries = cache
+        .debug_entries()
+        .map(|(&k, f, fifo)| (k, f, fifo))
+        .collect();
+    Trace {
+        hits,
+        lens,
+        entries,
+    }
+}
+
+#[test]
+fn reset_to_capacity_behaves_like_new() {
+    let mut cache = populated();
+    for cap in [50, 0, 1, 5, 2000, 37] {
+        cache.reset_to_capacity(cap);
+        let mut fresh = S3FIFO::new(cap);
+        #[cfg(debug_assertions)]
+        cache.assert_consistent();
+        assert_eq!(cache.describe(), fresh.describe(), "capacity {cap}");
+        assert_eq!(trace(&mut cache), trace(&mut fresh), "capacity {cap}");
+        #[cfg(debug_assertions)]
+        cache.assert_consistent();
+    }
+}
+
+#[test]
+fn set_small_ratio_keeps_the_surviving_keys() {
+    let mut cache = populated();
+    for ratio in [0.5, 0.05, 0.3, 0.01, 0.99] {
+        let before: Vec<u32> = cache.keys().copied().collect();
+        cache.set_small_ratio(ratio);
+        #[cfg(debug_assertions)]
+        cache.assert_consistent();
+        assert_eq!(cache.capacity(), 1000);
+        assert_eq!(
+            cache.small_capacity(),
+            ((1000.0 * ratio) as usize).max(1),
+            "ratio {ratio}"
+        );
+        assert!(cache.small_len() <= cache.small_capacity());
+        assert!(cache.main_len() <= cache.main_capacity());
+
+        // Shrinking a FIFO evicts from it, every surviving key keeps its value
+        let mut survivors = 0;
+        for k in before {
+            if let Some(&v) = cache.peek(&k) {
+                assert_eq!(v, k * 2);
+                survivors += 1;
+            }
+        }
+        assert_eq!(survivors, cache.len(), "ratio {ratio}");
+    }
+}
@@ -0,0 +1,110 @@
+//! Tests of the serde support
+
+#![cfg(feature = "serde")]
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::BuildHasherDefault;
+
+use s3fifo::{Fifo, S3FIFO};
+
+/// Deterministic hash builder, such that the restored ghost FIFO stays meaningful
+type Fixed = BuildHasherDefault<DefaultHasher>;
+
+type Cache = S3FIFO<u32, String, Fixed>;
+
+/// Keys, values, frequencies and FIFOs of the entries in FIFO order
+fn entries(cache: &Cache) -> Vec<(u32, String, u8, Fifo)> {
+    cache
+        .debug_entries()
+        .zip(cache.iter())
+        .map(|((&k, freq, fifo), (_, v))| (k, v.clone(), freq, fifo))
+        .collect()
+}
+
+fn error(json: &str) -> String {
+    serde_json::from_str::<Cache>(json)
+        .err()
+        .expect("malformed input is deserialized")
+        .to_string()
+}
+
+#[test]
+fn serde_round_trip() {
+    let mut cache = S3FIFO::with_hasher(100, Fixed::default());
+    for k in 0..50 {
+        cache.put(k, k.to_string());
+        if k % 3 == 0 {
+            cache.get(&k);
+        }
+    }
+    for k in 0..50 {
+        cache.put(k, format!("again {k}"));
+    }
+
+    let json = serde_json::to_string(&cache).unwrap();
+    let restored: Cache = serde_json::from_str(&json).unwrap();
+    #[cfg(debug_assertions)]
+    restored.assert_consistent();
+    assert_eq!(entries(&restored), entries(&cache));
+    assert_eq!(restored.capacity(), cache.capacity());
+    assert_eq!(restored.ghost_len(), cache.ghost_len());
+    for k in 0..50 {
+        assert_eq!(restored.in_ghost(&k), cache.in_ghost(&k));
+    }
+    assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+}
+
+#[test]
+fn huge_capacity_only_allocates_the_restored_entries() {
+    let json = r#"{
+        "config": { "capacity": 1099511627776 },
+        "small": [[7, "seven", 1]],
+        "main": [],
+        "ghost": [1, 2, 3]
+    }"#;
+    let mut cache: Cache = serde_json::from_str(json).unwrap();
+    assert_eq!(cache.capacity(), 1 << 40);
+    assert!(
+        cache.memory_footprint() < 4096,
+        "{}",
+        cache.memory_footprint()
+    );
+    assert_eq!(cache.get(&7).map(String::as_str), Some("seven"));
+    assert_eq!(cache.ghost_len(), 3);
+}
+
+#[test]
+fn malformed_inputs_are_rejected() {
+    let invalid_config = r#"{
+        "config": { "capacity": 100, "small_ratio": 1.5 },
+        "small": [], "main": [], "ghost": []
+    }"#;
+    assert!(error(invalid_config).contains("small ratio"));
+
+    let small: Vec<_> = (0..11).map(|k| (k, "", 0)).collect();
+    let too_long = format!(
+        r#"{{ "config": {{ "capacity": 100 }}, "small": {}, "main": [], "ghost": [] }}"#,
+        serde_json::to_string(&small).unwrap()
+    );
+    assert!(error(&too_long).contains("exceeds its capacity"));
+
+    let ghost: Vec<u32> = (0..91).collect();
+    let long_ghost = format!(
+        r#"{{ "config": {{ "capacity": 100 }}, "small": [], "main": [], "ghost": {} }}"#,
+        serde_json::to_string(&ghost).unwrap()
+    );
+    assert!(error(&long_ghost).contains("exceeds its capacity"));
+
+    let duplicate = r#"{
+        "config": { "capacity": 100 },
+        "small": [[7, "a", 0]], "main": [[7, "b", 0]], "ghost": []
+    }"#;
+    assert!(error(duplicate).contains("duplicate key"));
+
+    let wrong_type = r#"{
+        "config": { "capacity": 100 },
+        "small": [["seven", "a", 0]], "main": [], "ghost": []
+    }"#;
+    assert!(error(wrong_type).contains("invalid type"));
+    assert!(error(r#"{ "config": { "capacity": 100 }, "small": [] }"#).contains("missing field"));
+}
@@ -0,0 +1,46 @@
+//! Tests of `shrink_to_fit`
+
+use s3fifo::{Fifo, S3FIFO};
+
+fn keys(cache: &S3FIFO<u32, u32>) -> Vec<(u32, Fifo)> {
+    cache
+        .debug_entries()
+        .map(|(&k, _, fifo)| (k, fifo))
+        .collect()
+}
+
+#[test]
+fn shrink_to_fit_releases_the_memory_of_the_removed_entries() {
+    let mut cache = S3FIFO::new(10_000);
+    for k in 0..10_000 {
+        cache.put(k, k);
+        cache.get(&k);
+    }
+    cache.retain(|&k, _| k % 100 == 0);
+    let before = keys(&cache);
+    let footprint = cache.memory_footprint();
+
+    // The ghost FIFO keeps the hashes of the evicted keys
+    cache.shrink_to_fit();
+    let shrunk = cache.memory_footprint();
+    assert!(
+        shrunk < footprint / 2,
+        "{shrunk} is not well below {footprint}"
+    );
+    // Only the allocations shrink, the entries, their order and the capacity are kept
+    assert_eq!(keys(&cache), before);
+    assert_eq!(cache.capacity(), 10_000);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+    for (k, _) in before {
+        assert_eq!(cache.get(&k), Some(&k));
+    }
+
+    // The next insertions grow the allocations again
+    for k in 10_000..20_000 {
+        cache.put(k, k);
+    }
+    assert!(cache.memory_footprint() > shrunk);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
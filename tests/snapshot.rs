@@ -0,0 +1,134 @@
+//! Tests of the binary snapshot
+
+#![cfg(feature = "std")]
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::BuildHasherDefault;
+use std::io::ErrorKind;
+
+use s3fifo::{Fifo, S3FIFO};
+
+/// Deterministic hash builder, such that the restored ghost FIFO stays meaningful
+type Fixed = BuildHasherDefault<DefaultHasher>;
+
+/// Keys, values, frequencies and FIFOs of the entries in FIFO order
+fn entries(cache: &S3FIFO<u32, String, Fixed>) -> Vec<(u32, String, u8, Fifo)> {
+    cache
+        .debug_entries()
+        .zip(cache.iter())
+        .map(|((&k, freq, fifo), (_, v))| (k, v.clone(), freq, fifo))
+        .collect()
+}
+
+/// Header of a snapshot with the default configuration and given capacity
+fn header(capacity: u64, small_ratio: f64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&capacity.to_le_bytes());
+    bytes.extend_from_slice(&small_ratio.to_le_bytes());
+    bytes.extend_from_slice(&1.0f64.to_le_bytes());
+    bytes.extend_from_slice(&(1u64 << 22).to_le_bytes());
+    bytes.extend_from_slice(&[3, 1, 1]);
+    bytes
+}
+
+/// Append the FIFO with one bucket holding the key
+fn one_bucket(bytes: &mut Vec<u8>, key: u32) {
+    bytes.extend_from_slice(&1u64.to_le_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(&4u64.to_le_bytes());
+    bytes.extend_from_slice(&key.to_le_bytes());
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+}
+
+fn read(bytes: &[u8]) -> std::io::Result<S3FIFO<u32, String, Fixed>> {
+    S3FIFO::read_snapshot(bytes, Fixed::default())
+}
+
+fn error_kind(bytes: &[u8]) -> ErrorKind {
+    read(bytes)
+        .err()
+        .expect("malformed snapshot is read")
+        .kind()
+}
+
+#[test]
+fn snapshot_round_trip() {
+    let mut cache = S3FIFO::with_hasher(100, Fixed::default());
+    for k in 0..50 {
+        cache.put(k, k.to_string());
+        if k % 3 == 0 {
+            cache.get(&k);
+        }
+    }
+    for k in 0..50 {
+        cache.put(k, format!("again {k}"));
+    }
+    let mut bytes = Vec::new();
+    cache.write_snapshot(&mut bytes).unwrap();
+
+    let restored = read(&bytes).unwrap();
+    #[cfg(debug_assertions)]
+    restored.assert_consistent();
+    assert_eq!(entries(&restored), entries(&cache));
+    assert_eq!(restored.capacity(), cache.capacity());
+    assert_eq!(restored.ghost_len(), cache.ghost_len());
+    for k in 0..50 {
+        assert_eq!(restored.in_ghost(&k), cache.in_ghost(&k));
+    }
+}
+
+#[test]
+fn huge_capacity_only_allocates_the_restored_entries() {
+    let mut bytes = header(1 << 40, 0.1);
+    one_bucket(&mut bytes, 7);
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+
+    let mut cache = read(&bytes).unwrap();
+    assert_eq!(cache.capacity(), 1 << 40);
+    assert!(
+        cache.memory_footprint() < 4096,
+        "{}",
+        cache.memory_footprint()
+    );
+    assert_eq!(cache.get(&7).map(String::as_str), Some(""));
+    cache.put(8, String::new());
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn malformed_snapshots_are_rejected() {
+    // Invalid configuration
+    let mut bytes = header(100, 1.5);
+    bytes.extend_from_slice(&[0; 24]);
+    assert_eq!(error_kind(&bytes), ErrorKind::InvalidData);
+
+    // More buckets than the small FIFO holds
+    let mut bytes = header(100, 0.1);
+    bytes.extend_from_slice(&11u64.to_le_bytes());
+    assert_eq!(error_kind(&bytes), ErrorKind::InvalidData);
+
+    // More hashes than the ghost FIFO holds
+    let mut bytes = header(100, 0.1);
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&(1u64 << 40).to_le_bytes());
+    assert_eq!(error_kind(&bytes), ErrorKind::InvalidData);
+
+    // Duplicate key
+    let mut bytes = header(100, 0.1);
+    one_bucket(&mut bytes, 7);
+    one_bucket(&mut bytes, 7);
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    assert_eq!(error_kind(&bytes), ErrorKind::InvalidData);
+
+    // Truncated snapshot, and a key longer than the rest of the input
+    let mut bytes = header(100, 0.1);
+    one_bucket(&mut bytes, 7);
+    assert_eq!(error_kind(&bytes), ErrorKind::UnexpectedEof);
+    let mut bytes = header(100, 0.1);
+    bytes.extend_from_slice(&1u64.to_le_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+    assert_eq!(error_kind(&bytes), ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,15 @@
+//! Tests of the counters of the `stats` feature
+
+#![cfg(feature = "stats")]
+
+use s3fifo::S3FIFO;
+
+#[test]
+fn every_change_of_the_sizes_counts_as_a_resize() {
+    let mut cache: S3FIFO<u32, u32> = S3FIFO::new(100);
+    cache.resize(200);
+    cache.set_small_ratio(0.2);
+    cache.reset_to_capacity(50);
+    assert_eq!(cache.stats().resizes, 3);
+    assert_eq!(cache.capacity(), 50);
+}
@@ -0,0 +1,86 @@
+//! Tests of the thread safe caches
+
+#![cfg(feature = "std")]
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::PoisonError;
+use std::thread;
+
+use s3fifo::{ShardedS3FIFO, SyncS3FIFO, S3FIFO};
+
+#[test]
+fn threads_hammer_the_same_keys() {
+    let cache = SyncS3FIFO::new(64);
+    thread::scope(|scope| {
+        for t in 0..8u64 {
+            let cache = &cache;
+            scope.spawn(move || {
+                for i in 0..10_000u64 {
+                    let k = (i * 7 + t) % 100;
+                    if let Some(v) = cache.get(&k) {
+                        assert_eq!(v, k * 2);
+                    } else {
+                        cache.put(k, k * 2);
+                    }
+                    if i % 10 == 0 {
+                        cache.remove(&k);
+                    }
+                    assert!(cache.len() <= 64);
+                }
+            });
+        }
+    });
+    let cache = cache.into_inner().unwrap();
+    assert!(cache.len() <= 64);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+#[test]
+fn threads_hammer_the_shards() {
+    let cache = ShardedS3FIFO::new(64, 4);
+    thread::scope(|scope| {
+        for t in 0..8u64 {
+            let cache = &cache;
+            scope.spawn(move || {
+                for i in 0..10_000u64 {
+                    let k = (i * 7 + t) % 100;
+                    if let Some(v) = cache.get(&k) {
+                        assert_eq!(v, k * 2);
+                    } else {
+                        cache.put(k, k * 2);
+                    }
+                    assert!(cache.len() <= 64);
+                }
+            });
+        }
+    });
+    assert!(cache.len() <= 64);
+}
+
+#[test]
+fn a_panic_under_the_lock_poisons_the_cache() {
+    let weighted = S3FIFO::new_weighted(100, |_: &u32, v: &Vec<u8>| {
+        assert!(v.len() < 10, "too heavy");
+        v.len()
+    });
+    let cache = SyncS3FIFO::from(weighted);
+    cache.put(1, vec![0; 3]);
+
+    thread::scope(|scope| {
+        let panicked = scope.spawn(|| cache.put(2, vec![0; 20])).join();
+        assert!(panicked.is_err());
+    });
+    assert!(cache.is_poisoned());
+    assert!(cache.lock().is_err());
+    let get = panic::catch_unwind(AssertUnwindSafe(|| cache.get(&1)));
+    assert!(get.is_err(), "a poisoned cache must not be used");
+
+    // Clearing the cache through the poisoned guard recovers it
+    cache.lock().unwrap_or_else(PoisonError::into_inner).clear();
+    cache.clear_poison();
+    assert!(!cache.is_poisoned());
+    cache.put(3, vec![0; 3]);
+    assert_eq!(cache.get(&3), Some(vec![0; 3]));
+    assert_eq!(cache.len(), 1);
+}
@@ -0,0 +1,44 @@
+//! Tests of `try_put`, which never grows the allocations of the cache
+
+use s3fifo::S3FIFO;
+
+#[test]
+fn try_put_keeps_the_footprint_of_a_fresh_cache() {
+    let mut cache: S3FIFO<u64, u64> = S3FIFO::new(100);
+    let footprint = cache.memory_footprint();
+    for k in 0..1000 {
+        let inserted = cache.try_put(k, k).is_ok();
+        // Everything is allocated upfront, only the tombstones the evictions leave in
+        // the table may use up its growth budget, which takes more than 100 insertions
+        assert!(inserted || k >= 100, "try_put({k}) was refused");
+        assert_eq!(
+            cache.memory_footprint(),
+            footprint,
+            "try_put({k}) allocated"
+        );
+    }
+}
+
+#[test]
+fn try_put_refuses_absent_keys_after_shrink_to_fit() {
+    let mut cache: S3FIFO<u64, u64> = S3FIFO::new(100);
+    for k in 0..50 {
+        cache.put(k, k);
+    }
+    cache.shrink_to_fit();
+    let footprint = cache.memory_footprint();
+    let keys: Vec<u64> = cache.iter().map(|(&k, _)| k).collect();
+
+    for k in 1000..1100 {
+        assert_eq!(cache.try_put(k, k), Err((k, k)));
+        assert_eq!(
+            cache.memory_footprint(),
+            footprint,
+            "try_put({k}) allocated"
+        );
+    }
+    // The present keys are still replaced in place
+    assert_eq!(cache.try_put(keys[0], 7), Ok(Some(keys[0])));
+    assert_eq!(cache.memory_footprint(), footprint);
+    assert_eq!(cache.len(), keys.len());
+}
@@ -0,0 +1,97 @@
+//! Tests of the high and low watermarks
+
+use s3fifo::{Fifo, S3FIFO};
+
+/// Cache with capacity 100 holding the keys 0 to 9 in the small FIFO and 100 to 139 in
+/// the main FIFO
+fn filled() -> S3FIFO<u32, u32> {
+    let mut cache = S3FIFO::new(100);
+    for k in 100..140 {
+        cache.put(k, k);
+        cache.get(&k);
+        cache.get(&k);
+    }
+    for k in 0..10 {
+        cache.put(k, k);
+    }
+    assert_eq!((cache.small_len(), cache.main_len()), (10, 40));
+    cache
+}
+
+#[test]
+fn put_refreshing_sheds_on_every_path() {
+    // Absent key, which evicts from the full small FIFO
+    let mut cache = filled();
+    cache.set_high_watermark(49);
+    cache.set_low_watermark(45);
+    assert_eq!(cache.put_refreshing(1000, 1000), None);
+    assert_eq!(cache.len(), 45);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+
+    // Present key in the small FIFO
+    let mut cache = filled();
+    cache.set_high_watermark(40);
+    let small_key = cache
+        .debug_entries()
+        .find(|&(_, _, fifo)| fifo == Fifo::Small)
+        .map(|(&k, _, _)| k)
+        .unwrap();
+    assert_eq!(cache.put_refreshing(small_key, 0), Some(small_key));
+    assert_eq!(cache.len(), 40);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+
+    // Present key in the main FIFO
+    let mut cache = filled();
+    cache.set_high_watermark(40);
+    assert_eq!(cache.put_refreshing(139, 0), Some(139));
+    assert_eq!(cache.len(), 40);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+#[test]
+fn watermarks_shed_the_small_fifo_first() {
+    // The weight of an entry is its value, the small FIFO weighs 10 and the main 90
+    let mut cache = S3FIFO::new_weighted(100, |_, &v: &u32| v as usize);
+    for k in 100..110 {
+        cache.put(k, 5);
+        cache.get(&k);
+        cache.get(&k);
+    }
+    for (k, v) in [(0, 2), (1, 3), (2, 4)] {
+        cache.put(k, v);
+    }
+    assert_eq!(
+        (cache.small_len(), cache.main_len(), cache.weight()),
+        (3, 10, 59)
+    );
+
+    // Crossing the high watermark sheds the whole small FIFO, oldest first, then the
+    // front of the main FIFO until the weight is down to the low watermark
+    cache.set_high_watermark(50);
+    cache.set_low_watermark(35);
+    let (_, evicted) = cache.put_evict(3, 1);
+    assert_eq!(
+        evicted,
+        [(0, 2), (1, 3), (2, 4), (3, 1), (100, 5), (101, 5), (102, 5)]
+    );
+    assert_eq!(cache.weight(), 35);
+    let left: Vec<_> = cache
+        .debug_entries()
+        .map(|(&k, _, fifo)| (k, fifo))
+        .collect();
+    assert_eq!(
+        left,
+        (103..110).map(|k| (k, Fifo::Main)).collect::<Vec<_>>()
+    );
+    assert!((0..4).all(|k| cache.in_ghost(&k)));
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+
+    // Below the high watermark nothing is shed
+    let (_, evicted) = cache.put_evict(4, 10);
+    assert!(evicted.is_empty());
+    assert_eq!(cache.weight(), 45);
+}
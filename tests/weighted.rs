@@ -0,0 +1,103 @@
+//! Tests of the caches whose capacity is a total weight
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use s3fifo::{Fifo, PutResult, S3FIFO};
+
+/// Weighted cache of byte vectors that counts the calls of its weigher
+fn counting_cache(max_weight: usize) -> (S3FIFO<u32, Vec<u8>>, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let weigher_calls = Arc::clone(&calls);
+    let cache = S3FIFO::new_weighted(max_weight, move |_: &u32, v: &Vec<u8>| {
+        weigher_calls.fetch_add(1, Ordering::Relaxed);
+        v.len()
+    });
+    (cache, calls)
+}
+
+#[test]
+fn removal_keeps_the_weight_computed_at_insertion() {
+    let (mut cache, calls) = counting_cache(100);
+    cache.put(1, vec![0; 5]);
+    cache.put(2, vec![0; 3]);
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+    assert_eq!(cache.weight(), 8);
+
+    // Growing a value in place does not weigh it again
+    cache.get_mut(&1).unwrap().extend([0; 4]);
+    assert_eq!(cache.weight(), 8);
+    assert_eq!(cache.remove(&1).map(|v| v.len()), Some(9));
+    assert_eq!(cache.weight(), 3);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+
+    // Neither does replacing a value or evicting
+    cache.put(2, vec![0; 4]);
+    assert_eq!(cache.weight(), 4);
+    for k in 10..100 {
+        cache.put(k, vec![0; 2]);
+    }
+    assert_eq!(calls.load(Ordering::Relaxed), 3 + 90);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}
+
+#[test]
+fn put_checked_rejects_a_value_heavier_than_its_fifo() {
+    // The small FIFO weighs 10 and the main FIFO 90
+    let (mut cache, _) = counting_cache(100);
+    // Putting 5 to 8 promotes 0 to 3 into the main FIFO and evicts 4 into the ghost
+    // FIFO
+    for k in 0..9 {
+        cache.put(k, vec![0; 2]);
+        if k < 4 {
+            cache.get(&k);
+            cache.get(&k);
+        }
+    }
+    assert_eq!((cache.small_len(), cache.main_len()), (4, 4));
+    let snapshot = |cache: &S3FIFO<u32, Vec<u8>>| {
+        let entries = cache
+            .debug_entries()
+            .map(|(&k, freq, fifo)| (k, cache.peek(&k).unwrap().len(), freq, fifo))
+            .collect::<Vec<_>>();
+        (entries, cache.weight(), cache.ghost_len())
+    };
+    let before = snapshot(&cache);
+
+    // Heavier than the whole budget, or than the small FIFO a new key enters
+    for len in [101, 11] {
+        match cache.put_checked(100, vec![1; len]) {
+            PutResult::Rejected(k, v) => assert_eq!((k, v.len()), (100, len)),
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(snapshot(&cache), before);
+        assert!(!cache.in_ghost(&100));
+    }
+    // A present key heavier than its FIFO keeps its old value
+    assert_eq!(
+        cache.put_checked(0, vec![1; 91]),
+        PutResult::Rejected(0, vec![1; 91])
+    );
+    assert_eq!(snapshot(&cache), before);
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+
+    // Whatever fits goes through
+    assert_eq!(
+        cache.put_checked(0, vec![1; 90]),
+        PutResult::Replaced(vec![0; 2])
+    );
+    assert_eq!(cache.put_checked(100, vec![1; 10]), PutResult::Inserted);
+    assert_eq!(
+        cache
+            .debug_entries()
+            .find(|&(&k, _, _)| k == 100)
+            .unwrap()
+            .2,
+        Fifo::Small
+    );
+    #[cfg(debug_assertions)]
+    cache.assert_consistent();
+}